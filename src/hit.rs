@@ -1,6 +1,8 @@
+use crate::intersections::intersection::SurfacePoint;
 use crate::objects::RenderObject;
 use crate::utils::vec_format;
-use crate::{utils, Ray};
+use crate::ray::RayDifferential;
+use crate::{utils, Ray, Vec3Colour};
 use glam::{Vec2, Vec3};
 use std::fmt::Formatter;
 
@@ -12,12 +14,57 @@ pub struct Hit {
     pub original_normal: Vec3,
     pub uv: Vec2,
     pub uv_derivatives: (Vec3, Vec3),
+    /// Colour the geometry itself carries at the impact, e.g. vertex colours
+    pub surface_colour: Option<Vec3Colour>,
+    /// How the impact and uv change from one pixel to the next, when the ray carried differentials
+    pub differentials: Option<SurfaceDifferentials>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceDifferentials {
+    pub dp_dx: Vec3,
+    pub dp_dy: Vec3,
+    pub duv_dx: Vec2,
+    pub duv_dy: Vec2,
+}
+
+impl SurfaceDifferentials {
+    /// Where the ray's offset rays cross the plane the hit is on, and the uv there
+    fn new(ray: Ray, impact: Vec3, normal: Vec3, (dp_du, dp_dv): (Vec3, Vec3)) -> Option<Self> {
+        let differential = ray.differential()?;
+        let on_plane = |origin: Vec3, direction: Vec3| {
+            let facing = normal.dot(direction);
+            (facing.abs() > 1e-6).then(|| origin + direction * normal.dot(impact - origin) / facing - impact)
+        };
+        let dp_dx = on_plane(differential.rx_origin, differential.rx_direction)?;
+        let dp_dy = on_plane(differential.ry_origin, differential.ry_direction)?;
+
+        // Least squares for dp = du * dp_du + dv * dp_dv
+        let (a, b, c) = (dp_du.dot(dp_du), dp_du.dot(dp_dv), dp_dv.dot(dp_dv));
+        let det = a * c - b * b;
+        let to_uv = |dp: Vec3| {
+            if det.abs() < 1e-12 {
+                return Vec2::ZERO;
+            }
+            let (r1, r2) = (dp_du.dot(dp), dp_dv.dot(dp));
+            Vec2::new(c * r1 - b * r2, a * r2 - b * r1) / det
+        };
+
+        Some(Self {
+            dp_dx,
+            dp_dy,
+            duv_dx: to_uv(dp_dx),
+            duv_dy: to_uv(dp_dy),
+        })
+    }
 }
 
 impl Hit {
-    pub fn new(object: &RenderObject, intersection: Vec3, ray: Ray) -> Self {
-        let normal = object.intersector.normal_at(intersection).normalize();
+    pub fn new(object: &RenderObject, surface: SurfacePoint, ray: Ray) -> Self {
+        let intersection = surface.impact;
+        let normal = surface.normal.normalize();
         let uv = object.intersector.uv(intersection);
+        let uv_derivatives = object.intersector.uv_derivatives(uv);
 
         Hit {
             ray,
@@ -25,15 +72,102 @@ impl Hit {
             normal: utils::fix_normal(normal, ray.direction()).normalize(),
             original_normal: normal,
             uv,
-            uv_derivatives: object.intersector.uv_derivatives(uv),
+            uv_derivatives,
+            surface_colour: surface.surface_colour,
+            differentials: SurfaceDifferentials::new(ray, intersection, normal, uv_derivatives),
+        }
+    }
+
+    /// A hit in the middle of a medium rather than on a surface, there's no normal so it faces back along the ray
+    pub fn new_in_medium(point: Vec3, ray: Ray) -> Self {
+        let normal = -ray.direction();
+        Hit {
+            ray,
+            impact: point,
+            normal,
+            original_normal: normal,
+            uv: Vec2::ZERO,
+            uv_derivatives: (Vec3::X, Vec3::Y),
+            surface_colour: None,
+            differentials: None,
+        }
+    }
+    /// A point on a surface not found by tracing a ray, for looking textures up at e.g. mesh vertices.
+    /// It's seen head on from outside.
+    pub fn new_on_surface(point: Vec3, normal: Vec3, uv: Vec2) -> Self {
+        let (tangent, bitangent, _) = utils::build_orthonormal_basis(normal);
+        Hit {
+            ray: Ray::new(point + normal, -normal),
+            impact: point,
+            normal,
+            original_normal: normal,
+            uv,
+            uv_derivatives: (tangent, bitangent),
+            surface_colour: None,
+            differentials: None,
         }
     }
+
     pub fn on_outside(&self) -> bool {
         self.normal.dot(self.original_normal) >= 0.
     }
     pub fn direction(&self) -> Vec3 {
         self.ray.direction()
     }
+
+    /// Width of the ray's cone where it hit
+    pub fn footprint(&self) -> f32 {
+        self.ray.width_at(self.impact.distance(self.ray.start()))
+    }
+
+    /// How much of the uv space the ray covers along u and v. From the differentials where there are some,
+    /// otherwise the ray's cone, stretched where it hits at a glancing angle. Never more than the whole of
+    /// uv, and zero along a direction the surface doesn't move with (like u at a sphere's poles).
+    pub fn uv_footprint(&self) -> Vec2 {
+        let footprint = match self.differentials {
+            Some(differentials) => differentials.duv_dx.abs().max(differentials.duv_dy.abs()),
+            None => {
+                let cos = self.direction().dot(self.normal).abs().max(0.05);
+                let width = self.footprint() / cos;
+                let across = |dp: Vec3| {
+                    let length = dp.length();
+                    if length > f32::EPSILON { width / length } else { 0.0 }
+                };
+                let (du, dv) = self.uv_derivatives;
+                Vec2::new(across(du), across(dv))
+            }
+        };
+        footprint.min(Vec2::ONE)
+    }
+
+    /// The ray's differential after a mirror reflection about `normal`
+    pub fn reflected_differential(&self, normal: Vec3) -> Option<RayDifferential> {
+        let (differential, surface) = (self.ray.differential()?, self.differentials?);
+        Some(RayDifferential {
+            rx_origin: self.impact + surface.dp_dx,
+            rx_direction: differential.rx_direction.reflect(normal),
+            ry_origin: self.impact + surface.dp_dy,
+            ry_direction: differential.ry_direction.reflect(normal),
+        })
+    }
+
+    /// The ray's differential after refracting through `normal` (facing the ray), `eta` being the ratio of
+    /// the refractive index the ray is in over the one it's going into
+    pub fn refracted_differential(&self, normal: Vec3, eta: f32) -> Option<RayDifferential> {
+        let (differential, surface) = (self.ray.differential()?, self.differentials?);
+        let rx_direction = differential.rx_direction.refract(normal, eta);
+        let ry_direction = differential.ry_direction.refract(normal, eta);
+        // An offset ray was totally internally reflected while the main one wasn't
+        if rx_direction == Vec3::ZERO || ry_direction == Vec3::ZERO {
+            return None;
+        }
+        Some(RayDifferential {
+            rx_origin: self.impact + surface.dp_dx,
+            rx_direction,
+            ry_origin: self.impact + surface.dp_dy,
+            ry_direction,
+        })
+    }
 }
 
 impl std::fmt::Display for Hit {
@@ -9,11 +9,10 @@ pub struct AABB {
 }
 
 impl AABB {
-    pub fn new(triangles: &Vec<Triangle>) -> Self {
+    pub fn new(triangles: &[Triangle]) -> Self {
         let points = triangles
             .iter()
-            .map(|x| x.vertices())
-            .flatten()
+            .flat_map(|x| x.vertices())
             .collect::<Vec<_>>();
 
         assert!(!triangles.is_empty());
@@ -25,7 +24,24 @@ impl AABB {
         Self { min, max }
     }
 
+    pub fn from_min_max(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn min(&self) -> Vec3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Vec3 {
+        self.max
+    }
+
     pub fn intersects(&self, ray: Ray) -> bool {
+        self.intersection_range(ray).is_some()
+    }
+
+    /// Distances along the ray at which it enters and leaves the box, the entry is clamped to the ray start
+    pub fn intersection_range(&self, ray: Ray) -> Option<(f32, f32)> {
         // Compute inverse of each ray direction component
         let inv_dir_x = 1.0 / ray.direction().x;
         let inv_dir_y = 1.0 / ray.direction().y;
@@ -47,7 +63,7 @@ impl AABB {
 
         // If the intervals for x and y do not overlap, there is no intersection.
         if tmin > tymax || tymin > tmax {
-            return false;
+            return None;
         }
 
         // Update tmin and tmax to contain the overlap of x and y intervals.
@@ -67,10 +83,10 @@ impl AABB {
 
         // Check for overlap with the z interval.
         if tmin > tzmax || tzmin > tmax {
-            return false;
+            return None;
         }
 
-        // Optionally update tmin and tmax with z interval (not strictly needed for just a hit test)
+        // Update tmin and tmax with the z interval
         if tzmin > tmin {
             tmin = tzmin;
         }
@@ -80,10 +96,10 @@ impl AABB {
 
         // If tmax < 0, the entire intersection is behind the ray.
         if tmax < 0.0 {
-            return false;
+            return None;
         }
 
-        true
+        Some((tmin.max(0.0), tmax))
     }
 
     pub fn includes(&self, point: Vec3) -> bool {
@@ -1,7 +1,9 @@
 use crate::intersections::aabb::AABB;
-use crate::intersections::intersection::RenderIntersection;
+use crate::intersections::intersection::{RenderIntersection, SurfacePoint};
+use crate::intersections::mesh::{IndexedMesh, MeshProcessing, MeshStats};
 use crate::intersections::triangle::Triangle;
 use crate::*;
+use std::io;
 use std::path::Path;
 
 #[derive(Debug)]
@@ -20,6 +22,19 @@ impl AcceleratedPolygon {
         let polygon = Polygon::stl_to_points(path, scale, offset)?;
         Some(Self::from_triangles(polygon))
     }
+    pub fn new_from_mesh_file(path: impl AsRef<Path>, scale: f32, offset: Vec3) -> io::Result<Self> {
+        let triangles = Polygon::mesh_file_to_points(path, scale, offset)?;
+        Ok(Self::from_triangles(triangles))
+    }
+    pub fn new_smoothed_from_stl(
+        path: impl AsRef<Path>,
+        scale: f32,
+        offset: Vec3,
+        settings: MeshProcessing,
+    ) -> Option<(Self, MeshStats)> {
+        let (triangles, stats) = Polygon::stl_to_smooth_points(path, scale, offset, settings)?;
+        Some((Self::from_triangles(triangles), stats))
+    }
 }
 
 impl RenderIntersection for AcceleratedPolygon {
@@ -30,6 +45,13 @@ impl RenderIntersection for AcceleratedPolygon {
         self.polygon.intersects(ray)
     }
 
+    fn surface_hits(&self, ray: Ray) -> Vec<SurfacePoint> {
+        if !self.bounds.intersects(ray) {
+            return vec![];
+        }
+        self.polygon.surface_hits(ray)
+    }
+
     fn normal_at(&self, impact: Vec3) -> Vec3 {
         self.polygon.normal_at(impact)
     }
@@ -41,11 +63,20 @@ impl RenderIntersection for AcceleratedPolygon {
         self.polygon.includes_point_on_surface(point)
     }
 
-    fn uv(&self, at: Vec3) -> Vec2 {
-        todo!()
+    fn surface_colour(&self, at: Vec3) -> Option<Vec3Colour> {
+        self.polygon.surface_colour(at)
+    }
+
+    fn to_mesh(&self) -> Option<IndexedMesh> {
+        self.polygon.to_mesh()
+    }
+
+    /// Meshes carry no texture coordinates, texture them with a `Projected` texture instead
+    fn uv(&self, _at: Vec3) -> Vec2 {
+        Vec2::ZERO
     }
 
-    fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3) {
-        todo!()
+    fn uv_derivatives(&self, _uv: Vec2) -> (Vec3, Vec3) {
+        (Vec3::X, Vec3::Y)
     }
 }
@@ -0,0 +1,289 @@
+use crate::intersections::aabb::AABB;
+use crate::intersections::intersection::{RenderIntersection, OBJECT_TOLERANCE};
+use crate::intersections::mesh::IndexedMesh;
+use crate::intersections::triangle::Triangle;
+use crate::*;
+use glam::{IVec2, UVec2};
+use image::error::{ParameterError, ParameterErrorKind};
+use image::{ImageError, ImageResult};
+use std::path::Path;
+
+/// Terrain made from a regular grid of heights, z is up.
+/// Each grid cell is split into two triangles along its diagonal.
+#[derive(Debug)]
+pub struct Heightfield {
+    /// Row major, `resolution.x * resolution.y` samples already multiplied by the vertical scale
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    resolution: UVec2,
+    /// World position of the first sample at height 0
+    origin: Vec3,
+    /// Horizontal extent of the whole terrain
+    size: Vec2,
+    cell_size: Vec2,
+    bounds: AABB,
+}
+
+impl Heightfield {
+    /// Matches the minimum distance the scene accepts hits at, so rays leaving the terrain don't hit where they started
+    const MIN_HIT_DISTANCE: Length = 0.001;
+
+    /// `heights` is row major with `resolution.x * resolution.y` samples, at least 2x2
+    pub fn new(
+        heights: Vec<f32>,
+        resolution: UVec2,
+        origin: Vec3,
+        horizontal_scale: Vec2,
+        vertical_scale: f32,
+    ) -> ImageResult<Self> {
+        let error = |message: String| {
+            ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(message)))
+        };
+        if resolution.x < 2 || resolution.y < 2 {
+            return Err(error(format!(
+                "a heightfield needs at least 2x2 samples, not {}x{}",
+                resolution.x, resolution.y
+            )));
+        }
+        if heights.len() as u64 != resolution.x as u64 * resolution.y as u64 {
+            return Err(error(format!(
+                "a {}x{} heightfield needs {} samples, not {}",
+                resolution.x,
+                resolution.y,
+                resolution.x as u64 * resolution.y as u64,
+                heights.len()
+            )));
+        }
+
+        let heights = heights.into_iter().map(|h| h * vertical_scale).collect::<Vec<_>>();
+        let cell_size = horizontal_scale / (resolution - 1).as_vec2();
+
+        let (low, high) = heights
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), h| (lo.min(*h), hi.max(*h)));
+        let bounds = AABB::from_min_max(
+            origin + Vec3::new(0.0, 0.0, low - OBJECT_TOLERANCE),
+            origin + horizontal_scale.extend(high + OBJECT_TOLERANCE),
+        );
+
+        let mut heightfield = Self {
+            heights,
+            normals: vec![],
+            resolution,
+            origin,
+            size: horizontal_scale,
+            cell_size,
+            bounds,
+        };
+        heightfield.normals = heightfield.compute_normals();
+        Ok(heightfield)
+    }
+
+    /// Reads a greyscale image where black is height 0 and white is `vertical_scale`.
+    /// Image x runs along +x and image y along +y, `horizontal_scale` is the world size of the whole image.
+    pub fn new_from_image(
+        path: impl AsRef<Path>,
+        origin: Vec3,
+        horizontal_scale: Vec2,
+        vertical_scale: f32,
+    ) -> ImageResult<Self> {
+        let image = image::open(path)?.to_luma32f();
+        let resolution = UVec2::new(image.width(), image.height());
+        let heights = image.pixels().map(|p| p.0[0]).collect();
+        Self::new(heights, resolution, origin, horizontal_scale, vertical_scale)
+    }
+
+    fn height(&self, x: u32, y: u32) -> f32 {
+        let x = x.min(self.resolution.x - 1);
+        let y = y.min(self.resolution.y - 1);
+        self.heights[(y * self.resolution.x + x) as usize]
+    }
+
+    fn vertex(&self, x: u32, y: u32) -> Vec3 {
+        self.origin + (UVec2::new(x, y).as_vec2() * self.cell_size).extend(self.height(x, y))
+    }
+
+    fn normal(&self, x: u32, y: u32) -> Vec3 {
+        self.normals[(y * self.resolution.x + x) as usize]
+    }
+
+    /// Central differences, falling back to one sided differences on the edges
+    fn compute_normals(&self) -> Vec<Vec3> {
+        let [nx, ny] = self.resolution.to_array();
+        (0..ny)
+            .flat_map(|y| (0..nx).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(nx - 1));
+                let (y0, y1) = (y.saturating_sub(1), (y + 1).min(ny - 1));
+                let dh_dx = (self.height(x1, y) - self.height(x0, y))
+                    / ((x1 - x0) as f32 * self.cell_size.x);
+                let dh_dy = (self.height(x, y1) - self.height(x, y0))
+                    / ((y1 - y0) as f32 * self.cell_size.y);
+                Vec3::new(-dh_dx, -dh_dy, 1.0).normalize()
+            })
+            .collect()
+    }
+
+    fn cell_triangles(&self, cell: UVec2) -> [Triangle; 2] {
+        let [x, y] = cell.to_array();
+        let (v00, v10) = (self.vertex(x, y), self.vertex(x + 1, y));
+        let (v01, v11) = (self.vertex(x, y + 1), self.vertex(x + 1, y + 1));
+        [Triangle::new([v00, v10, v11]), Triangle::new([v00, v11, v01])]
+    }
+
+    /// The cell a point lies over and the position within that cell in [0, 1]
+    fn locate(&self, at: Vec3) -> (UVec2, Vec2) {
+        let grid = (at.truncate() - self.origin.truncate()) / self.cell_size;
+        let cell = grid
+            .floor()
+            .clamp(Vec2::ZERO, (self.resolution - 2).as_vec2())
+            .as_uvec2();
+        let fraction = (grid - cell.as_vec2()).clamp(Vec2::ZERO, Vec2::ONE);
+        (cell, fraction)
+    }
+
+    /// Barycentric weights of the four cell corners (00, 10, 01, 11) for the triangle the point falls in
+    fn corner_weights(fraction: Vec2) -> [f32; 4] {
+        let [fx, fy] = fraction.to_array();
+        if fx >= fy {
+            [1.0 - fx, fx - fy, 0.0, fy]
+        } else {
+            [1.0 - fy, 0.0, fy - fx, fx]
+        }
+    }
+
+    fn interpolate<T>(&self, at: Vec3, value: impl Fn(u32, u32) -> T) -> T
+    where
+        T: std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+    {
+        let (cell, fraction) = self.locate(at);
+        let [x, y] = cell.to_array();
+        let [w00, w10, w01, w11] = Self::corner_weights(fraction);
+        value(x, y) * w00 + value(x + 1, y) * w10 + value(x, y + 1) * w01 + value(x + 1, y + 1) * w11
+    }
+
+    fn first_hit_in_cell(&self, ray: Ray, cell: UVec2) -> Option<(Vec3, Length)> {
+        self.cell_triangles(cell)
+            .iter()
+            .flat_map(|triangle| triangle.intersects(ray))
+            .map(|point| (point, point.distance(ray.start())))
+            .filter(|(_, distance)| *distance >= Self::MIN_HIT_DISTANCE)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    /// 2D DDA over the grid cells under the ray, testing the two triangles of each cell in order
+    fn traverse(&self, ray: Ray) -> Option<Vec3> {
+        let (t_enter, t_exit) = self.bounds.intersection_range(ray)?;
+        let direction = ray.direction().truncate();
+        let start = ray.pos_at_length(t_enter);
+
+        let last_cell = (self.resolution - 2).as_ivec2();
+        let mut cell = self.locate(start).0.as_ivec2();
+        let step = IVec2::new(
+            if direction.x >= 0.0 { 1 } else { -1 },
+            if direction.y >= 0.0 { 1 } else { -1 },
+        );
+
+        // Distance along the ray to the next cell boundary on each axis, and between boundaries
+        let boundary = |cell: IVec2| {
+            let next = (cell + step.max(IVec2::ZERO)).as_vec2() * self.cell_size + self.origin.truncate();
+            let t = (next - ray.start().truncate()) / direction;
+            Vec2::select(direction.cmpeq(Vec2::ZERO), Vec2::INFINITY, t)
+        };
+        let t_delta = Vec2::select(
+            direction.cmpeq(Vec2::ZERO),
+            Vec2::INFINITY,
+            (self.cell_size / direction).abs(),
+        );
+        let mut t_max = boundary(cell);
+
+        while cell.cmpge(IVec2::ZERO).all() && cell.cmple(last_cell).all() {
+            // Cells are visited front to back, so the first hit found is the closest
+            if let Some((point, _)) = self.first_hit_in_cell(ray, cell.as_uvec2()) {
+                return Some(point);
+            }
+            if t_max.min_element() > t_exit {
+                break;
+            }
+            if t_max.x < t_max.y {
+                cell.x += step.x;
+                t_max.x += t_delta.x;
+            } else {
+                cell.y += step.y;
+                t_max.y += t_delta.y;
+            }
+        }
+        None
+    }
+}
+
+impl RenderIntersection for Heightfield {
+    fn intersects(&self, ray: Ray) -> Vec<Vec3> {
+        self.traverse(ray).into_iter().collect()
+    }
+
+    /// Vertex normals interpolated across the triangle, so the terrain shades smoothly
+    fn normal_at(&self, impact: Vec3) -> Vec3 {
+        self.interpolate(impact, |x, y| self.normal(x, y)).normalize()
+    }
+
+    fn to_mesh(&self) -> Option<IndexedMesh> {
+        let [nx, ny] = self.resolution.to_array();
+        let vertices = (0..ny)
+            .flat_map(|y| (0..nx).map(move |x| (x, y)))
+            .map(|(x, y)| self.vertex(x, y))
+            .collect();
+        let index = |x: u32, y: u32| (y * nx + x) as usize;
+        let faces = (0..ny - 1)
+            .flat_map(|y| (0..nx - 1).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let (v00, v10, v01, v11) = (index(x, y), index(x + 1, y), index(x, y + 1), index(x + 1, y + 1));
+                [[v00, v10, v11], [v00, v11, v01]]
+            })
+            .collect();
+        let mut mesh = IndexedMesh::new(vertices, faces);
+        mesh.normals = Some(self.normals.clone());
+        Some(mesh)
+    }
+
+    fn includes_point_on_surface(&self, point: Vec3) -> bool {
+        let local = point.truncate() - self.origin.truncate();
+        if local.cmplt(Vec2::ZERO).any() || local.cmpgt(self.size).any() {
+            return false;
+        }
+        let height = self.interpolate(point, |x, y| self.height(x, y)) + self.origin.z;
+        (height - point.z).abs() <= OBJECT_TOLERANCE
+    }
+
+    /// The image is stretched once over the whole terrain
+    fn uv(&self, at: Vec3) -> Vec2 {
+        (at.truncate() - self.origin.truncate()) / self.size
+    }
+
+    fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3) {
+        let at = self.origin + (uv * self.size).extend(0.0);
+        let (cell, _) = self.locate(at);
+        let [x, y] = cell.to_array();
+        let dh_du = (self.height(x + 1, y) - self.height(x, y)) * (self.resolution.x - 1) as f32;
+        let dh_dv = (self.height(x, y + 1) - self.height(x, y)) * (self.resolution.y - 1) as f32;
+        (
+            Vec3::new(self.size.x, 0.0, dh_du),
+            Vec3::new(0.0, self.size.y, dh_dv),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_checks_its_samples() {
+        let new = |heights: Vec<f32>, resolution: UVec2| {
+            Heightfield::new(heights, resolution, Vec3::ZERO, Vec2::ONE, 1.0)
+        };
+        assert!(new(vec![0.0; 4], UVec2::new(2, 2)).is_ok());
+        assert!(new(vec![0.0; 3], UVec2::new(3, 1)).is_err());
+        assert!(new(vec![0.0; 5], UVec2::new(2, 2)).is_err());
+    }
+}
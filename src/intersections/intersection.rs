@@ -1,11 +1,31 @@
-use crate::hit::Hit;
+use crate::intersections::mesh::IndexedMesh;
 use crate::Ray;
 use crate::Vec2;
 use crate::Vec3;
+use crate::Vec3Colour;
 use std::fmt::Debug;
 
 pub(crate) const OBJECT_TOLERANCE: f32 = 0.0001;
 
+/// Where a ray meets an object, along with what the object knows about its surface there
+#[derive(Debug, Clone, Copy)]
+pub struct SurfacePoint {
+    pub impact: Vec3,
+    pub normal: Vec3,
+    pub surface_colour: Option<Vec3Colour>,
+}
+
+impl SurfacePoint {
+    /// Asks the object about the surface at a point it was hit at
+    pub fn at(object: &(impl RenderIntersection + ?Sized), impact: Vec3) -> Self {
+        Self {
+            impact,
+            normal: object.normal_at(impact),
+            surface_colour: object.surface_colour(impact),
+        }
+    }
+}
+
 pub trait RenderIntersection: Debug + Sync {
     fn intersects(&self, ray: Ray) -> Vec<Vec3>;
     fn normal_at(&self, impact: Vec3) -> Vec3;
@@ -16,5 +36,24 @@ pub trait RenderIntersection: Debug + Sync {
     /// t, b, normal are all perpendicular
     /// returns (right, up)
     fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3);
+
+    /// `intersects` along with the surface at each point. Objects made of many parts override this to shade
+    /// from the part the ray hit, rather than looking for it again by position.
+    fn surface_hits(&self, ray: Ray) -> Vec<SurfacePoint> {
+        self.intersects(ray)
+            .into_iter()
+            .map(|impact| SurfacePoint::at(self, impact))
+            .collect()
+    }
+
+    /// Colour baked into the geometry itself, like the vertex colours of a scanned mesh
+    fn surface_colour(&self, _at: Vec3) -> Option<Vec3Colour> {
+        None
+    }
+
+    /// The triangles making up the object, for objects that are made of triangles, so they can be exported
+    fn to_mesh(&self) -> Option<IndexedMesh> {
+        None
+    }
 }
 
@@ -0,0 +1,515 @@
+use crate::hit::Hit;
+use crate::intersections::triangle::Triangle;
+use crate::materials::texture::Texture;
+use crate::*;
+use glam::{I64Vec3, Mat4};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// How much each face contributes to the normals of its corners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalWeighting {
+    /// Bigger faces count for more
+    Area,
+    /// Faces count by the angle they make at the vertex, which doesn't depend on how the mesh was tessellated
+    Angle,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MeshProcessing {
+    /// Vertices closer than this are merged into one
+    pub weld_tolerance: Length,
+    /// Triangles with less area than this are dropped
+    pub min_area: f32,
+    /// Faces meeting at a sharper angle than this (in degrees) keep a hard edge between them
+    pub crease_angle: Angle,
+    pub weighting: NormalWeighting,
+}
+
+impl Default for MeshProcessing {
+    fn default() -> Self {
+        Self {
+            weld_tolerance: 1e-5,
+            min_area: 1e-8,
+            crease_angle: 60.0,
+            weighting: NormalWeighting::Angle,
+        }
+    }
+}
+
+/// What happened to a mesh while it was processed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MeshStats {
+    pub input_triangles: usize,
+    pub output_triangles: usize,
+    /// Corners of the input triangles, every one is its own vertex in an STL
+    pub input_vertices: usize,
+    pub welded_vertices: usize,
+    /// Triangles that collapsed to a line or point, or fell below the minimum area
+    pub degenerate_triangles: usize,
+}
+
+impl Display for MeshStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} triangles ({} degenerate removed), {} -> {} vertices",
+            self.input_triangles,
+            self.output_triangles,
+            self.degenerate_triangles,
+            self.input_vertices,
+            self.welded_vertices,
+        )
+    }
+}
+
+/// Triangles sharing vertices by index, rather than each holding its own copies
+#[derive(Debug, Clone)]
+pub struct IndexedMesh {
+    pub vertices: Vec<Vec3>,
+    pub faces: Vec<[usize; 3]>,
+    /// Per vertex normals, when the file had them
+    pub normals: Option<Vec<Vec3>>,
+    /// Per vertex linear colours, when the file had them
+    pub colours: Option<Vec<Vec3Colour>>,
+}
+
+impl IndexedMesh {
+    pub fn new(vertices: Vec<Vec3>, faces: Vec<[usize; 3]>) -> Self {
+        Self {
+            vertices,
+            faces,
+            normals: None,
+            colours: None,
+        }
+    }
+
+    /// Every triangle keeps its own vertices. Normals and colours are kept if every triangle has them.
+    pub fn from_triangles(triangles: &[Triangle]) -> Self {
+        let vertices = triangles.iter().flat_map(|t| t.vertices()).collect();
+        let faces = (0..triangles.len()).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect();
+        let normals = triangles
+            .iter()
+            .map(|t| t.normals())
+            .collect::<Option<Vec<_>>>()
+            .map(|x| x.concat());
+        let colours = triangles
+            .iter()
+            .map(|t| t.colours())
+            .collect::<Option<Vec<_>>>()
+            .map(|x| x.concat());
+        Self {
+            vertices,
+            faces,
+            normals,
+            colours,
+        }
+    }
+
+    /// Joins meshes into one, normals and colours are kept if every mesh has them
+    pub fn merge(meshes: Vec<IndexedMesh>) -> Self {
+        let keep_normals = meshes.iter().all(|m| m.normals.is_some());
+        let keep_colours = meshes.iter().all(|m| m.colours.is_some());
+
+        let mut merged = Self::new(vec![], vec![]);
+        merged.normals = keep_normals.then(Vec::new);
+        merged.colours = keep_colours.then(Vec::new);
+
+        for mesh in meshes {
+            let offset = merged.vertices.len();
+            merged.faces.extend(mesh.faces.iter().map(|face| face.map(|i| i + offset)));
+            merged.vertices.extend(mesh.vertices);
+            if let (Some(all), Some(normals)) = (&mut merged.normals, mesh.normals) {
+                all.extend(normals);
+            }
+            if let (Some(all), Some(colours)) = (&mut merged.colours, mesh.colours) {
+                all.extend(colours);
+            }
+        }
+        merged
+    }
+
+    /// Applies an arbitrary affine transform, normals go through the inverse transpose so they stay perpendicular
+    pub fn with_transform(mut self, transform: Mat4) -> Self {
+        let normal_transform = transform.inverse().transpose();
+        self.vertices.iter_mut().for_each(|v| *v = transform.transform_point3(*v));
+        if let Some(normals) = &mut self.normals {
+            normals
+                .iter_mut()
+                .for_each(|n| *n = normal_transform.transform_vector3(*n).normalize_or_zero());
+        }
+        self
+    }
+
+    /// Unit normal of a face from its winding
+    pub fn face_normal(&self, face: [usize; 3]) -> Vec3 {
+        let [a, b, c] = self.corners(face);
+        (b - a).cross(c - a).normalize_or_zero()
+    }
+
+    /// Scales then moves every vertex, the same way the STL loaders do
+    pub fn transformed(mut self, scale: f32, offset: Vec3) -> Self {
+        self.vertices.iter_mut().for_each(|v| *v = *v * scale + offset);
+        self
+    }
+
+    /// Triangles carrying whatever normals and colours the mesh has
+    pub fn triangles(&self) -> Vec<Triangle> {
+        self.faces
+            .iter()
+            .map(|face| {
+                let vertices = self.corners(*face);
+                let triangle = match &self.normals {
+                    Some(normals) => Triangle::new_with_normals(vertices, face.map(|i| normals[i])),
+                    None => Triangle::new(vertices),
+                };
+                match &self.colours {
+                    Some(colours) => triangle.with_colours(face.map(|i| colours[i])),
+                    None => triangle,
+                }
+            })
+            .collect()
+    }
+
+    /// Merges the corners of unconnected triangles that lie within the tolerance of each other,
+    /// then drops whatever triangles became degenerate.
+    /// Only positions survive, this is for formats like STL that don't have anything else.
+    pub fn weld(triangles: &[[Vec3; 3]], settings: MeshProcessing) -> (Self, MeshStats) {
+        let (vertices, remap) = weld_points(triangles.iter().flatten().copied(), settings.weld_tolerance);
+        let welded = (0..triangles.len())
+            .map(|t| [0, 1, 2].map(|corner| remap[t * 3 + corner]))
+            .collect::<Vec<_>>();
+
+        let mesh = Self::new(vertices, welded);
+        let faces = mesh
+            .faces
+            .iter()
+            .copied()
+            .filter(|face| !mesh.is_degenerate(*face, settings.min_area))
+            .collect::<Vec<_>>();
+
+        let stats = MeshStats {
+            input_triangles: triangles.len(),
+            output_triangles: faces.len(),
+            input_vertices: triangles.len() * 3,
+            welded_vertices: mesh.vertices.len(),
+            degenerate_triangles: triangles.len() - faces.len(),
+        };
+        (Self::new(mesh.vertices, faces), stats)
+    }
+
+    /// The same mesh with vertices closer than the tolerance merged, so faces that only touched by
+    /// position share their vertices. Meshes from STLs and `from_triangles` give every triangle its own
+    /// corners, and need this before anything that walks between neighbouring faces.
+    /// Merged vertices keep the normal and colour of the first of them, faces that collapse are dropped.
+    pub fn welded(&self, tolerance: Length) -> Self {
+        let (vertices, remap) = weld_points(self.vertices.iter().copied(), tolerance);
+        let mut first = vec![usize::MAX; vertices.len()];
+        for (old, new) in remap.iter().enumerate().rev() {
+            first[*new] = old;
+        }
+        let faces = self
+            .faces
+            .iter()
+            .map(|face| face.map(|i| remap[i]))
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect();
+        Self {
+            vertices,
+            faces,
+            normals: self.normals.as_ref().map(|normals| first.iter().map(|i| normals[*i]).collect()),
+            colours: self.colours.as_ref().map(|colours| first.iter().map(|i| colours[*i]).collect()),
+        }
+    }
+
+    fn corners(&self, face: [usize; 3]) -> [Vec3; 3] {
+        face.map(|i| self.vertices[i])
+    }
+
+    fn is_degenerate(&self, face: [usize; 3], min_area: f32) -> bool {
+        let [a, b, c] = face;
+        if a == b || b == c || a == c {
+            return true;
+        }
+        let [a, b, c] = self.corners(face);
+        (b - a).cross(c - a).length() * 0.5 < min_area
+    }
+
+    /// Interior angle of the face at corner `i`
+    fn corner_angle(&self, face: [usize; 3], i: usize) -> f32 {
+        let corners = self.corners(face);
+        let (p, a, b) = (corners[i], corners[(i + 1) % 3], corners[(i + 2) % 3]);
+        (a - p).angle_between(b - p)
+    }
+
+    /// A normal for each corner of each face, averaged over the neighbouring faces that aren't across a crease
+    pub fn vertex_normals(&self, settings: MeshProcessing) -> Vec<[Vec3; 3]> {
+        let face_normals = self
+            .faces
+            .iter()
+            .map(|face| self.face_normal(*face))
+            .collect::<Vec<_>>();
+
+        let mut faces_at_vertex: Vec<Vec<(usize, usize)>> = vec![vec![]; self.vertices.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            for (corner, v) in face.iter().enumerate() {
+                faces_at_vertex[*v].push((f, corner));
+            }
+        }
+
+        let cos_crease = settings.crease_angle.to_radians().cos();
+        let weight = |f: usize, corner: usize| match settings.weighting {
+            NormalWeighting::Area => {
+                let [a, b, c] = self.corners(self.faces[f]);
+                (b - a).cross(c - a).length() * 0.5
+            }
+            NormalWeighting::Angle => self.corner_angle(self.faces[f], corner),
+        };
+
+        self.faces
+            .iter()
+            .enumerate()
+            .map(|(f, face)| {
+                [0, 1, 2].map(|corner| {
+                    let smoothed = faces_at_vertex[face[corner]]
+                        .iter()
+                        .filter(|(g, _)| face_normals[*g].dot(face_normals[f]) >= cos_crease)
+                        .map(|(g, g_corner)| face_normals[*g] * weight(*g, *g_corner))
+                        .sum::<Vec3>();
+                    smoothed.try_normalize().unwrap_or(face_normals[f])
+                })
+            })
+            .collect()
+    }
+
+    /// Splits every triangle into four at its edges' midpoints, `levels` times over.
+    /// Normals and colours are interpolated onto the new vertices.
+    ///
+    /// The mesh is welded first with the default tolerance so neighbouring faces share their midpoints,
+    /// otherwise a mesh of separate triangles would come apart along every edge.
+    pub fn subdivided(&self, levels: u32) -> Self {
+        let mut mesh = self.welded(MeshProcessing::default().weld_tolerance);
+        for _ in 0..levels {
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut faces = Vec::with_capacity(mesh.faces.len() * 4);
+            for [a, b, c] in mesh.faces.clone() {
+                let mut midpoint = |i: usize, j: usize| {
+                    *midpoints.entry((i.min(j), i.max(j))).or_insert_with(|| {
+                        mesh.vertices.push((mesh.vertices[i] + mesh.vertices[j]) / 2.0);
+                        if let Some(normals) = &mut mesh.normals {
+                            normals.push((normals[i] + normals[j]).normalize_or_zero());
+                        }
+                        if let Some(colours) = &mut mesh.colours {
+                            colours.push((colours[i] + colours[j]) / 2.0);
+                        }
+                        mesh.vertices.len() - 1
+                    })
+                };
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                faces.extend([[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+            }
+            mesh.faces = faces;
+        }
+        mesh
+    }
+
+    /// One normal per vertex, from the mesh's own or averaged over the faces around it by area
+    fn smooth_vertex_normals(&self) -> Vec<Vec3> {
+        if let Some(normals) = &self.normals {
+            return normals.clone();
+        }
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for face in &self.faces {
+            let [a, b, c] = self.corners(*face);
+            let area_weighted = (b - a).cross(c - a);
+            face.iter().for_each(|v| normals[*v] += area_weighted);
+        }
+        normals.iter().map(|x| x.normalize_or_zero()).collect()
+    }
+
+    /// Moves every vertex along its normal by the texture's value there times `scale`, for real geometric
+    /// detail rather than a bump map's shading trick. Subdivide first so there are vertices to move.
+    ///
+    /// Meshes have no uv, so the texture is looked up by position and normal: use one in world space,
+    /// like a procedural or projected texture. The old normals no longer fit and are dropped.
+    ///
+    /// Like `subdivided`, this welds the mesh first so each position moves once along one normal and the
+    /// surface stays closed.
+    pub fn displaced(self, height: &dyn Texture, scale: f32) -> Self {
+        let mut mesh = self.welded(MeshProcessing::default().weld_tolerance);
+        let normals = mesh.smooth_vertex_normals();
+        mesh.vertices.iter_mut().zip(normals).for_each(|(vertex, normal)| {
+            let hit = Hit::new_on_surface(*vertex, normal, Vec2::ZERO);
+            *vertex += normal * height.sample_scalar(&hit) * scale;
+        });
+        mesh.normals = None;
+        mesh
+    }
+
+    /// Triangles with interpolated normals, ready for a `Polygon`
+    pub fn smooth_triangles(&self, settings: MeshProcessing) -> Vec<Triangle> {
+        self.faces
+            .iter()
+            .zip(self.vertex_normals(settings))
+            .map(|(face, normals)| Triangle::new_with_normals(self.corners(*face), normals))
+            .collect()
+    }
+}
+
+/// Merges points within the tolerance of each other. Gives the merged points and, for each input point,
+/// the index of the one it became.
+fn weld_points(points: impl Iterator<Item = Vec3>, tolerance: Length) -> (Vec<Vec3>, Vec<usize>) {
+    let tolerance = tolerance.max(f32::EPSILON);
+    // 64 bit keys, 32 bit ones saturate for coordinates past about 2e4 at the default tolerance, piling
+    // every far away vertex into the same few cells
+    let cell_of = |p: Vec3| (p.as_dvec3() / tolerance as f64).floor().as_i64vec3();
+
+    // Spatial hash with cells as big as the tolerance, so a match is always in a neighbouring cell
+    let mut cells: HashMap<I64Vec3, Vec<usize>> = HashMap::new();
+    let mut vertices: Vec<Vec3> = vec![];
+
+    let remap = points
+        .map(|p| {
+            let cell = cell_of(p);
+            let existing = (-1..=1)
+                .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| I64Vec3::new(x, y, z))))
+                .filter_map(|offset| cells.get(&(cell + offset)))
+                .flatten()
+                .find(|i| vertices[**i].distance(p) <= tolerance)
+                .copied();
+            existing.unwrap_or_else(|| {
+                vertices.push(p);
+                cells.entry(cell).or_default().push(vertices.len() - 1);
+                vertices.len() - 1
+            })
+        })
+        .collect();
+    (vertices, remap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit cube around the origin as 12 separate triangles wound outwards, the way an STL has it
+    fn cube() -> Vec<[Vec3; 3]> {
+        let mut triangles = vec![];
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            for normal in [axis, -axis] {
+                let (u, v) = (Vec3::new(axis.z, axis.x, axis.y), Vec3::new(axis.y, axis.z, axis.x));
+                let (u, v) = if u.cross(v).dot(normal) > 0.0 { (u, v) } else { (v, u) };
+                let corner = |s: f32, t: f32| (normal + u * s + v * t) * 0.5;
+                let (a, b, c, d) = (corner(-1., -1.), corner(1., -1.), corner(1., 1.), corner(-1., 1.));
+                triangles.extend([[a, b, c], [a, c, d]]);
+            }
+        }
+        triangles
+    }
+
+    /// Edges used by only one face, which a closed surface has none of
+    fn boundary_edges(mesh: &IndexedMesh) -> usize {
+        let mut uses: HashMap<(usize, usize), usize> = HashMap::new();
+        for [a, b, c] in &mesh.faces {
+            for (i, j) in [(*a, *b), (*b, *c), (*c, *a)] {
+                *uses.entry((i.min(j), i.max(j))).or_default() += 1;
+            }
+        }
+        uses.values().filter(|x| **x == 1).count()
+    }
+
+    fn unwelded_cube() -> IndexedMesh {
+        let triangles = cube();
+        let faces = (0..triangles.len()).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect();
+        IndexedMesh::new(triangles.concat(), faces)
+    }
+
+    #[test]
+    fn subdividing_separate_triangles_shares_their_edges() {
+        let mesh = unwelded_cube();
+        assert_eq!(boundary_edges(&mesh), 36);
+        let subdivided = mesh.subdivided(2);
+        assert_eq!(subdivided.faces.len(), 12 * 16);
+        assert_eq!(boundary_edges(&subdivided), 0);
+    }
+
+    #[test]
+    fn displacing_a_closed_mesh_keeps_it_closed() {
+        let displaced = unwelded_cube().subdivided(1).displaced(&0.5f32, 0.1);
+        assert_eq!(boundary_edges(&displaced), 0);
+
+        // Straight from separate triangles too, every copy of a corner has to move the same way
+        let displaced = unwelded_cube().displaced(&0.5f32, 0.1);
+        assert_eq!(displaced.vertices.len(), 8);
+        assert_eq!(boundary_edges(&displaced), 0);
+    }
+
+    #[test]
+    fn welding_an_stl_cube_shares_its_corners() {
+        let (mesh, stats) = IndexedMesh::weld(&cube(), MeshProcessing::default());
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.faces.len(), 12);
+        assert_eq!(stats.input_vertices, 36);
+        assert_eq!(stats.welded_vertices, 8);
+        assert_eq!(stats.degenerate_triangles, 0);
+        assert_eq!(boundary_edges(&mesh), 0);
+    }
+
+    #[test]
+    fn welding_stays_within_the_tolerance() {
+        let settings = MeshProcessing {
+            weld_tolerance: 0.01,
+            ..MeshProcessing::default()
+        };
+        let triangle = |shift: Vec3| [Vec3::ZERO, Vec3::X, Vec3::Y].map(|p| p + shift);
+
+        let (mesh, _) = IndexedMesh::weld(&[triangle(Vec3::ZERO), triangle(Vec3::splat(0.005))], settings);
+        assert_eq!(mesh.vertices.len(), 3);
+
+        let (mesh, _) = IndexedMesh::weld(&[triangle(Vec3::ZERO), triangle(Vec3::splat(0.02))], settings);
+        assert_eq!(mesh.vertices.len(), 6);
+    }
+
+    #[test]
+    fn degenerate_triangles_are_dropped_and_counted() {
+        let mut triangles = cube();
+        // A sliver that welds down to a line, and one too small to keep
+        triangles.push([Vec3::ZERO, Vec3::X, Vec3::X * 1.000001]);
+        triangles.push([Vec3::ZERO, Vec3::X * 1e-5, Vec3::Y * 1e-5]);
+        let (mesh, stats) = IndexedMesh::weld(&triangles, MeshProcessing::default());
+        assert_eq!(mesh.faces.len(), 12);
+        assert_eq!(stats.input_triangles, 14);
+        assert_eq!(stats.output_triangles, 12);
+        assert_eq!(stats.degenerate_triangles, 2);
+    }
+
+    #[test]
+    fn creases_keep_flat_normals_and_gentle_angles_smooth() {
+        let (mesh, _) = IndexedMesh::weld(&cube(), MeshProcessing::default());
+        // The cube's 90 degree edges are sharper than the 60 degree crease angle
+        for (face, normals) in mesh.faces.iter().zip(mesh.vertex_normals(MeshProcessing::default())) {
+            let flat = mesh.face_normal(*face);
+            assert!(normals.iter().all(|n| n.abs_diff_eq(flat, 1e-5)), "{normals:?} != {flat}");
+        }
+
+        // Past 90 degrees every corner averages its three sides
+        let smooth = MeshProcessing {
+            crease_angle: 100.0,
+            ..MeshProcessing::default()
+        };
+        for (face, normals) in mesh.faces.iter().zip(mesh.vertex_normals(smooth)) {
+            for (corner, normal) in face.iter().zip(normals) {
+                let diagonal = mesh.vertices[*corner].normalize();
+                assert!(normal.abs_diff_eq(diagonal, 1e-5), "{normal} != {diagonal}");
+            }
+        }
+    }
+
+    #[test]
+    fn welding_far_from_the_origin_keeps_distinct_vertices_apart() {
+        let far = Vec3::new(1e5, -3e5, 2e5);
+        let triangle = [far, far + Vec3::new(0.5, 0.0, 0.0), far + Vec3::new(0.0, 0.5, 0.0)];
+        let (mesh, stats) = IndexedMesh::weld(&[triangle], MeshProcessing::default());
+        assert_eq!(mesh.vertices.len(), 3);
+        assert_eq!(stats.degenerate_triangles, 0);
+    }
+}
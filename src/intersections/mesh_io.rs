@@ -0,0 +1,888 @@
+use crate::intersections::mesh::IndexedMesh;
+use crate::utils::{invalid_data, ColourChange};
+use crate::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Reads an STL (ASCII or binary), PLY (ASCII or binary) or OFF file, picked by extension.
+/// Errors say which file and, for text formats, which line went wrong.
+pub fn read_mesh(path: impl AsRef<Path>) -> io::Result<IndexedMesh> {
+    let path = path.as_ref();
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {e}", path.display()));
+    let bytes = std::fs::read(path).map_err(with_path)?;
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+
+    let mesh = match extension.as_deref() {
+        Some("stl") => read_stl(&bytes),
+        Some("ply") => read_ply(&bytes),
+        Some("off") => read_off(&bytes),
+        _ => Err(invalid_data("unrecognised mesh extension, expected .stl, .ply or .off")),
+    };
+    mesh.map_err(with_path)
+}
+
+/// Words of a text file along with the line they came from, with `#` comments removed
+fn tokens(text: &str) -> Vec<(usize, &str)> {
+    text.lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let line = line.split('#').next().unwrap_or("");
+            line.split_whitespace().map(move |word| (i + 1, word))
+        })
+        .collect()
+}
+
+fn parse<T: std::str::FromStr>(token: Option<&(usize, &str)>, what: &str) -> io::Result<T> {
+    match token {
+        Some((line, word)) => word
+            .parse()
+            .map_err(|_| invalid_data(format!("line {line}: expected {what}, found `{word}`"))),
+        None => Err(invalid_data(format!("unexpected end of file, expected {what}"))),
+    }
+}
+
+/// Splits a polygon into triangles around its first corner
+fn fan(polygon: &[usize]) -> impl Iterator<Item = [usize; 3]> + '_ {
+    (1..polygon.len().saturating_sub(1)).map(move |i| [polygon[0], polygon[i], polygon[i + 1]])
+}
+
+fn check_indices(mesh: &IndexedMesh) -> io::Result<()> {
+    let count = mesh.vertices.len();
+    match mesh.faces.iter().flatten().find(|i| **i >= count) {
+        Some(i) => Err(invalid_data(format!("face refers to vertex {i} but there are only {count}"))),
+        None => Ok(()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// STL
+// ---------------------------------------------------------------------------
+
+pub fn read_stl(bytes: &[u8]) -> io::Result<IndexedMesh> {
+    // Plenty of binary files start with "solid" too, so trust the triangle count if the file is big enough
+    // to hold that many. Some exporters pad the end, so there may be more. The count of an ASCII file is
+    // four letters of its header, billions of triangles, so it never fits.
+    let binary_size = |bytes: &[u8]| {
+        let count = u32::from_le_bytes(bytes.get(80..84)?.try_into().ok()?) as usize;
+        Some((count, count.checked_mul(50)?.checked_add(84)?))
+    };
+    let binary = binary_size(bytes);
+    if let Some((count, size)) = binary {
+        if bytes.len() >= size {
+            return Ok(read_binary_stl(&bytes[84..], count));
+        }
+    }
+    if bytes.trim_ascii_start().starts_with(b"solid") {
+        return match (std::str::from_utf8(bytes), binary) {
+            (Ok(text), _) => read_ascii_stl(text),
+            (Err(_), Some((count, size))) => Err(invalid_data(format!(
+                "binary STL of {count} triangles needs {size} bytes, but there are only {}",
+                bytes.len()
+            ))),
+            (Err(e), None) => Err(invalid_data(e.to_string())),
+        };
+    }
+    Err(invalid_data("neither an ASCII STL nor a binary STL of the right size"))
+}
+
+/// Facet normals are ignored, they're often missing or wrong and they're flat anyway
+fn read_binary_stl(records: &[u8], count: usize) -> IndexedMesh {
+    let float = |x: &[u8]| f32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+    let vertices = records
+        .chunks_exact(50)
+        .take(count)
+        .flat_map(|record| {
+            (0..3).map(move |v| {
+                let at = 12 + v * 12;
+                Vec3::new(
+                    float(&record[at..]),
+                    float(&record[at + 4..]),
+                    float(&record[at + 8..]),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let faces = (0..count).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect();
+    IndexedMesh::new(vertices, faces)
+}
+
+pub fn read_ascii_stl(text: &str) -> io::Result<IndexedMesh> {
+    let tokens = tokens(text);
+    let mut vertices = vec![];
+    let mut faces = vec![];
+    let mut loop_start = None;
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let (line, word) = tokens[i];
+        match word {
+            "outer" => loop_start = Some(vertices.len()),
+            "vertex" => {
+                let x = parse(tokens.get(i + 1), "a vertex coordinate")?;
+                let y = parse(tokens.get(i + 2), "a vertex coordinate")?;
+                let z = parse(tokens.get(i + 3), "a vertex coordinate")?;
+                vertices.push(Vec3::new(x, y, z));
+                i += 3;
+            }
+            "endloop" => {
+                let start = loop_start
+                    .take()
+                    .ok_or_else(|| invalid_data(format!("line {line}: `endloop` without `outer loop`")))?;
+                let corners = (start..vertices.len()).collect::<Vec<_>>();
+                if corners.len() < 3 {
+                    return Err(invalid_data(format!(
+                        "line {line}: facet has {} vertices, needs at least 3",
+                        corners.len()
+                    )));
+                }
+                faces.extend(fan(&corners));
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(IndexedMesh::new(vertices, faces))
+}
+
+// ---------------------------------------------------------------------------
+// PLY
+// http://paulbourke.net/dataformats/ply/
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> io::Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(invalid_data(format!("unknown PLY property type `{name}`"))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// What a colour stored in this type is divided by to get into [0, 1]
+    fn colour_range(self) -> f64 {
+        match self {
+            Self::U8 | Self::I8 => u8::MAX as f64,
+            Self::U16 | Self::I16 => u16::MAX as f64,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar { name: String, kind: PlyType },
+    List { name: String, count: PlyType, item: PlyType },
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+/// Pulls values out of the body of a PLY, whichever way it is encoded
+enum PlyBody<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], position: usize, big_endian: bool },
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, kind: PlyType) -> io::Result<f64> {
+        match self {
+            PlyBody::Ascii(words) => {
+                let word = words
+                    .next()
+                    .ok_or_else(|| invalid_data("PLY body ended early"))?;
+                word.parse()
+                    .map_err(|_| invalid_data(format!("expected a number in the PLY body, found `{word}`")))
+            }
+            PlyBody::Binary { bytes, position, big_endian } => {
+                let size = kind.size();
+                let mut raw = [0u8; 8];
+                raw[..size].copy_from_slice(
+                    bytes
+                        .get(*position..*position + size)
+                        .ok_or_else(|| invalid_data("PLY body ended early"))?,
+                );
+                *position += size;
+                if *big_endian {
+                    raw[..size].reverse();
+                }
+                Ok(match kind {
+                    PlyType::I8 => raw[0] as i8 as f64,
+                    PlyType::U8 => raw[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    PlyType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    PlyType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::F64 => f64::from_le_bytes(raw),
+                })
+            }
+        }
+    }
+}
+
+/// ASCII and binary PLY, keeping vertex normals (`nx ny nz`) and colours (`red green blue`) if present
+pub fn read_ply(bytes: &[u8]) -> io::Result<IndexedMesh> {
+    let (header, body) = split_ply_header(bytes)?;
+    let (format, elements) = parse_ply_header(header)?;
+
+    let mut body = match format {
+        PlyFormat::Ascii => PlyBody::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|e| invalid_data(e.to_string()))?
+                .split_ascii_whitespace(),
+        ),
+        PlyFormat::LittleEndian | PlyFormat::BigEndian => PlyBody::Binary {
+            bytes: body,
+            position: 0,
+            big_endian: format == PlyFormat::BigEndian,
+        },
+    };
+
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut colours = vec![];
+    let mut faces = vec![];
+    // Reused for every vertex and face
+    let mut scalars = vec![];
+    let mut polygon = vec![];
+
+    for element in &elements {
+        let scalar_index = |name: &str| {
+            element.properties.iter().position(|p| matches!(p, PlyProperty::Scalar { name: n, .. } if n == name))
+        };
+        let position = ["x", "y", "z"].map(scalar_index);
+        let normal = ["nx", "ny", "nz"].map(scalar_index);
+        let colour = ["red", "green", "blue"].map(scalar_index);
+        let colour_range = colour[0]
+            .and_then(|i| match element.properties[i] {
+                PlyProperty::Scalar { kind, .. } => Some(kind.colour_range()),
+                PlyProperty::List { .. } => None,
+            })
+            .unwrap_or(1.0);
+
+        for index in 0..element.count {
+            scalars.clear();
+            scalars.resize(element.properties.len(), 0.0);
+            polygon.clear();
+
+            for (p, property) in element.properties.iter().enumerate() {
+                match property {
+                    PlyProperty::Scalar { kind, .. } => scalars[p] = body.read(*kind)?,
+                    PlyProperty::List { name, count, item } => {
+                        let count = body.read(*count)? as usize;
+                        let indices = name == "vertex_indices" || name == "vertex_index";
+                        for _ in 0..count {
+                            let x = body.read(*item)?;
+                            if !indices {
+                                continue;
+                            }
+                            if x < 0.0 || x.fract() != 0.0 {
+                                return Err(invalid_data(format!("PLY face {index} has vertex index {x}")));
+                            }
+                            polygon.push(x as usize);
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let get = |i: Option<usize>| i.map(|i| scalars[i] as f32);
+                    let [x, y, z] = position.map(get);
+                    match (x, y, z) {
+                        (Some(x), Some(y), Some(z)) => vertices.push(Vec3::new(x, y, z)),
+                        _ => return Err(invalid_data(format!("PLY vertex {index} has no x, y and z"))),
+                    }
+                    if let [Some(x), Some(y), Some(z)] = normal.map(get) {
+                        normals.push(Vec3::new(x, y, z));
+                    }
+                    if let [Some(r), Some(g), Some(b)] = colour.map(get) {
+                        let srgb = Vec3::new(r, g, b) / colour_range as f32;
+                        colours.push(Srgb::new(srgb.x, srgb.y, srgb.z).to_vec3());
+                    }
+                }
+                "face" => faces.extend(fan(&polygon)),
+                _ => {}
+            }
+        }
+    }
+
+    let mut mesh = IndexedMesh::new(vertices, faces);
+    if !normals.is_empty() {
+        mesh.normals = Some(normals);
+    }
+    if !colours.is_empty() {
+        mesh.colours = Some(colours);
+    }
+    check_indices(&mesh)?;
+    Ok(mesh)
+}
+
+/// Splits a PLY into its header and body. The header ends at the first line that is just `end_header`,
+/// so a comment mentioning it doesn't cut the header short.
+fn split_ply_header(bytes: &[u8]) -> io::Result<(&str, &[u8])> {
+    let mut line_start = 0;
+    while line_start < bytes.len() {
+        let line_end = bytes[line_start..]
+            .iter()
+            .position(|x| *x == b'\n')
+            .map_or(bytes.len(), |x| line_start + x + 1);
+        if bytes[line_start..line_end].trim_ascii() == b"end_header" {
+            let header = std::str::from_utf8(&bytes[..line_start]).map_err(|e| invalid_data(e.to_string()))?;
+            return Ok((header, &bytes[line_end..]));
+        }
+        line_start = line_end;
+    }
+    Err(invalid_data("PLY header has no `end_header`"))
+}
+
+fn parse_ply_header(header: &str) -> io::Result<(PlyFormat, Vec<PlyElement>)> {
+    let mut lines = header.lines().enumerate();
+    if lines.next().map(|(_, x)| x.trim()) != Some("ply") {
+        return Err(invalid_data("missing `ply` magic"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+
+    for (i, line) in lines {
+        let line_number = i + 1;
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let error = |message: &str| invalid_data(format!("line {line_number}: {message}"));
+
+        match words.as_slice() {
+            ["format", kind, ..] => {
+                format = Some(match *kind {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::LittleEndian,
+                    "binary_big_endian" => PlyFormat::BigEndian,
+                    _ => return Err(error(&format!("unknown PLY format `{kind}`"))),
+                })
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| error("element count isn't a number"))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| error("property before any element"))?
+                .properties
+                .push(PlyProperty::List {
+                    name: name.to_string(),
+                    count: PlyType::parse(count)?,
+                    item: PlyType::parse(item)?,
+                }),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or_else(|| error("property before any element"))?
+                .properties
+                .push(PlyProperty::Scalar {
+                    name: name.to_string(),
+                    kind: PlyType::parse(kind)?,
+                }),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(error(&format!("unrecognised header line `{line}`"))),
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid_data("PLY header has no `format` line"))?;
+    Ok((format, elements))
+}
+
+// ---------------------------------------------------------------------------
+// OFF
+// https://segeval.cs.princeton.edu/public/off_format.html
+// ---------------------------------------------------------------------------
+
+/// OFF along with the COFF (vertex colours), NOFF (vertex normals) and CNOFF variants
+pub fn read_off(bytes: &[u8]) -> io::Result<IndexedMesh> {
+    let text = std::str::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))?;
+    let tokens = tokens(text);
+    let mut tokens = tokens.iter().peekable();
+
+    let magic = tokens.next().map(|(_, x)| *x).unwrap_or("");
+    let (has_colours, has_normals) = match magic {
+        "OFF" => (false, false),
+        "COFF" => (true, false),
+        "NOFF" => (false, true),
+        "CNOFF" => (true, true),
+        _ => return Err(invalid_data(format!("missing OFF magic, found `{magic}`"))),
+    };
+
+    let vertex_count: usize = parse(tokens.next(), "the vertex count")?;
+    let face_count: usize = parse(tokens.next(), "the face count")?;
+    let _edge_count: usize = parse(tokens.next(), "the edge count")?;
+
+    // Vertex alphas and face colours are optional extras at the end of a line, we don't use them
+    fn skip_rest_of_line<'a>(
+        tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a (usize, &'a str)>>,
+        line: usize,
+    ) {
+        while tokens.next_if(|(l, _)| *l == line).is_some() {}
+    }
+
+    let mut vertices = Vec::with_capacity(vertex_count);
+    let mut normals = Vec::with_capacity(vertex_count);
+    let mut colours = Vec::with_capacity(vertex_count);
+    // Colours are either 0-255 integers or 0-1 floats
+    let mut integer_colours = true;
+
+    for _ in 0..vertex_count {
+        let line = tokens.peek().map(|(line, _)| *line).unwrap_or(0);
+        let mut vector = |what| -> io::Result<Vec3> {
+            Ok(Vec3::new(
+                parse(tokens.next(), what)?,
+                parse(tokens.next(), what)?,
+                parse(tokens.next(), what)?,
+            ))
+        };
+        vertices.push(vector("a vertex coordinate")?);
+        if has_normals {
+            normals.push(vector("a vertex normal")?);
+        }
+        if has_colours {
+            let channels = [tokens.next(), tokens.next(), tokens.next()];
+            integer_colours &= channels.iter().flatten().all(|(_, x)| x.parse::<u32>().is_ok());
+            let [r, g, b] = channels.map(|x| parse(x, "a vertex colour"));
+            colours.push(Vec3::new(r?, g?, b?));
+        }
+        skip_rest_of_line(&mut tokens, line);
+    }
+
+    let mut faces = vec![];
+    for _ in 0..face_count {
+        let line = tokens.peek().map(|(line, _)| *line).unwrap_or(0);
+        let corners: usize = parse(tokens.next(), "the corner count of a face")?;
+        let polygon = (0..corners)
+            .map(|_| parse(tokens.next(), "a vertex index"))
+            .collect::<io::Result<Vec<usize>>>()?;
+        faces.extend(fan(&polygon));
+        skip_rest_of_line(&mut tokens, line);
+    }
+
+    let mut mesh = IndexedMesh::new(vertices, faces);
+    if has_normals {
+        mesh.normals = Some(normals);
+    }
+    if has_colours {
+        let range = if integer_colours { 255.0 } else { 1.0 };
+        mesh.colours = Some(
+            colours
+                .into_iter()
+                .map(|c| {
+                    let c = c / range;
+                    Srgb::new(c.x, c.y, c.z).to_vec3()
+                })
+                .collect(),
+        );
+    }
+    check_indices(&mesh)?;
+    Ok(mesh)
+}
+
+// ---------------------------------------------------------------------------
+// Writers
+// ---------------------------------------------------------------------------
+
+/// Writes a binary STL, OBJ or binary PLY, picked by extension.
+/// STL only has positions, OBJ and PLY also keep the normals and colours if the mesh has them.
+pub fn write_mesh(path: impl AsRef<Path>, mesh: &IndexedMesh) -> io::Result<()> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+
+    let write: fn(&mut BufWriter<File>, &IndexedMesh) -> io::Result<()> = match extension.as_deref() {
+        Some("stl") => write_stl,
+        Some("obj") => write_obj,
+        Some("ply") => write_ply,
+        _ => {
+            return Err(invalid_data(format!(
+                "{}: unrecognised mesh extension, expected .stl, .obj or .ply",
+                path.display()
+            )))
+        }
+    };
+
+    let mut file = BufWriter::new(File::create(path)?);
+    write(&mut file, mesh)?;
+    file.flush()
+}
+
+/// Colours are stored linear, files want them in sRGB
+fn srgb(colour: Vec3Colour) -> Srgb<f32> {
+    Srgb::from_vec3(colour.clamp(Vec3::ZERO, Vec3::ONE))
+}
+
+pub fn write_stl(out: &mut impl Write, mesh: &IndexedMesh) -> io::Result<()> {
+    let mut header = [0u8; 80];
+    let title = b"binary STL written by ray";
+    header[..title.len()].copy_from_slice(title);
+    out.write_all(&header)?;
+    let count = u32::try_from(mesh.faces.len())
+        .map_err(|_| invalid_data(format!("binary STL holds at most {} triangles, not {}", u32::MAX, mesh.faces.len())))?;
+    out.write_all(&count.to_le_bytes())?;
+
+    for face in &mesh.faces {
+        let normal = mesh.face_normal(*face);
+        let corners = face.map(|i| mesh.vertices[i]);
+        for v in std::iter::once(normal).chain(corners) {
+            for x in v.to_array() {
+                out.write_all(&x.to_le_bytes())?;
+            }
+        }
+        // Attribute byte count, unused
+        out.write_all(&[0, 0])?;
+    }
+    Ok(())
+}
+
+/// Vertex colours go after the position on the `v` line, which most tools understand
+pub fn write_obj(out: &mut impl Write, mesh: &IndexedMesh) -> io::Result<()> {
+    writeln!(out, "# written by ray")?;
+    for (i, v) in mesh.vertices.iter().enumerate() {
+        match &mesh.colours {
+            Some(colours) => {
+                let (r, g, b) = srgb(colours[i]).into_components();
+                writeln!(out, "v {} {} {} {r} {g} {b}", v.x, v.y, v.z)?
+            }
+            None => writeln!(out, "v {} {} {}", v.x, v.y, v.z)?,
+        }
+    }
+    if let Some(normals) = &mesh.normals {
+        for n in normals {
+            writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+    }
+    // OBJ indices start at 1
+    for face in &mesh.faces {
+        let [a, b, c] = face.map(|i| i + 1);
+        match mesh.normals {
+            Some(_) => writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}")?,
+            None => writeln!(out, "f {a} {b} {c}")?,
+        }
+    }
+    Ok(())
+}
+
+pub fn write_ply(out: &mut impl Write, mesh: &IndexedMesh) -> io::Result<()> {
+    writeln!(out, "ply")?;
+    writeln!(out, "format binary_little_endian 1.0")?;
+    writeln!(out, "comment written by ray")?;
+    writeln!(out, "element vertex {}", mesh.vertices.len())?;
+    for axis in ["x", "y", "z"] {
+        writeln!(out, "property float {axis}")?;
+    }
+    if mesh.normals.is_some() {
+        for axis in ["nx", "ny", "nz"] {
+            writeln!(out, "property float {axis}")?;
+        }
+    }
+    if mesh.colours.is_some() {
+        for channel in ["red", "green", "blue"] {
+            writeln!(out, "property uchar {channel}")?;
+        }
+    }
+    writeln!(out, "element face {}", mesh.faces.len())?;
+    writeln!(out, "property list uchar int vertex_indices")?;
+    writeln!(out, "end_header")?;
+
+    for (i, v) in mesh.vertices.iter().enumerate() {
+        let normal = mesh.normals.as_ref().map(|x| x[i]);
+        for x in std::iter::once(*v).chain(normal).flat_map(|v| v.to_array()) {
+            out.write_all(&x.to_le_bytes())?;
+        }
+        if let Some(colours) = &mesh.colours {
+            let (r, g, b) = Srgb::<u8>::from_vec3(colours[i]).into_components();
+            out.write_all(&[r, g, b])?;
+        }
+    }
+    for face in &mesh.faces {
+        out.write_all(&[3])?;
+        for i in face {
+            let i = i32::try_from(*i)
+                .map_err(|_| invalid_data(format!("PLY vertex indices are ints, vertex {i} doesn't fit")))?;
+            out.write_all(&i.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles sharing an edge, with normals and colours
+    fn square() -> IndexedMesh {
+        let mut mesh = IndexedMesh::new(
+            vec![Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y],
+            vec![[0, 1, 2], [0, 2, 3]],
+        );
+        mesh.normals = Some(vec![Vec3::Z; 4]);
+        mesh.colours = Some(vec![Vec3::X, Vec3::Y, Vec3::Z, Vec3::splat(0.5)]);
+        mesh
+    }
+
+    fn assert_close(a: &[Vec3], b: &[Vec3], tolerance: f32) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!(a.abs_diff_eq(*b, tolerance), "{a} != {b}");
+        }
+    }
+
+    fn written(write: fn(&mut Vec<u8>, &IndexedMesh) -> io::Result<()>, mesh: &IndexedMesh) -> Vec<u8> {
+        let mut bytes = vec![];
+        write(&mut bytes, mesh).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn ply_round_trip() {
+        let mesh = square();
+        let read = read_ply(&written(write_ply, &mesh)).unwrap();
+        assert_eq!(read.vertices, mesh.vertices);
+        assert_eq!(read.faces, mesh.faces);
+        assert_eq!(read.normals, mesh.normals);
+        // Colours go through 8 bit sRGB
+        assert_close(read.colours.as_ref().unwrap(), mesh.colours.as_ref().unwrap(), 0.01);
+    }
+
+    #[test]
+    fn ply_indices_that_dont_fit_an_int_are_errors() {
+        let mesh = IndexedMesh::new(vec![Vec3::ZERO, Vec3::X, Vec3::Y], vec![[0, 1, i32::MAX as usize + 1]]);
+        let error = write_ply(&mut vec![], &mesh).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn stl_round_trip() {
+        let mesh = square();
+        let read = read_stl(&written(write_stl, &mesh)).unwrap();
+        assert_eq!(read.faces.len(), 2);
+        let corners = |m: &IndexedMesh| m.faces.iter().flat_map(|f| f.map(|i| m.vertices[i])).collect::<Vec<_>>();
+        assert_eq!(corners(&read), corners(&mesh));
+        assert!(read.normals.is_none() && read.colours.is_none());
+    }
+
+    #[test]
+    fn binary_stl_with_padding_or_a_solid_header() {
+        let mut bytes = written(write_stl, &square());
+        bytes[..5].copy_from_slice(b"solid");
+        assert_eq!(read_stl(&bytes).unwrap().faces.len(), 2);
+
+        bytes.extend([0; 16]);
+        assert_eq!(read_stl(&bytes).unwrap().faces.len(), 2);
+
+        bytes.truncate(84 + 50);
+        let error = read_stl(&bytes).unwrap_err().to_string();
+        assert!(error.contains("needs 184 bytes"), "{error}");
+    }
+
+    #[test]
+    fn ascii_stl() {
+        let text = "solid test
+            facet normal 0 0 1
+              outer loop
+                vertex 0 0 0
+                vertex 1 0 0
+                vertex 1 1 0
+                vertex 0 1 0
+              endloop
+            endfacet
+            endsolid test";
+        let mesh = read_stl(text.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn ascii_ply_with_colours_and_normals() {
+        let text = "ply
+format ascii 1.0
+comment a square
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 255 0 0
+1 0 0 0 0 1 0 255 0
+1 1 0 0 0 1 0 0 255
+0 1 0 0 0 1 255 255 255
+4 0 1 2 3
+";
+        let mesh = read_ply(text.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices, square().vertices);
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.normals, Some(vec![Vec3::Z; 4]));
+        assert_close(
+            mesh.colours.as_ref().unwrap(),
+            &[Vec3::X, Vec3::Y, Vec3::Z, Vec3::ONE],
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn big_endian_ply() {
+        let mut bytes = b"ply
+format binary_big_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar uint vertex_indices
+end_header
+"
+        .to_vec();
+        for v in [Vec3::ZERO, Vec3::X, Vec3::Y] {
+            v.to_array().iter().for_each(|x| bytes.extend(x.to_be_bytes()));
+        }
+        bytes.push(3);
+        [0u32, 1, 2].iter().for_each(|i| bytes.extend(i.to_be_bytes()));
+
+        let mesh = read_ply(&bytes).unwrap();
+        assert_eq!(mesh.vertices, vec![Vec3::ZERO, Vec3::X, Vec3::Y]);
+        assert_eq!(mesh.faces, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn coff_with_alpha() {
+        let text = "COFF
+# vertices, faces, edges
+3 1 0
+0 0 0 255 0 0 255
+1 0 0 0 255 0 128
+0 1 0 0 0 255 0
+3 0 1 2
+";
+        let mesh = read_off(text.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices, vec![Vec3::ZERO, Vec3::X, Vec3::Y]);
+        assert_eq!(mesh.faces, vec![[0, 1, 2]]);
+        assert_close(mesh.colours.as_ref().unwrap(), &[Vec3::X, Vec3::Y, Vec3::Z], 1e-5);
+    }
+
+    #[test]
+    fn off_colours_are_bytes_only_when_written_as_integers() {
+        let coff = |colours: [&str; 3]| {
+            format!(
+                "COFF\n3 1 0\n0 0 0 {}\n1 0 0 {}\n0 1 0 {}\n3 0 1 2\n",
+                colours[0], colours[1], colours[2]
+            )
+        };
+        let colours = |text: String| read_off(text.as_bytes()).unwrap().colours.unwrap();
+
+        // Floats are 0-1 even when every channel is a whole number
+        let floats = colours(coff(["1.0 0.0 0.0", "0 1.0 0", "0.0 0.0 1.0"]));
+        assert_close(&floats, &[Vec3::X, Vec3::Y, Vec3::Z], 1e-5);
+        // Integers are 0-255 even when they're all small
+        let bytes = colours(coff(["1 0 0", "0 1 0", "0 0 1"]));
+        let one = Srgb::new(1.0 / 255.0, 0.0, 0.0).to_vec3().x;
+        assert_close(&bytes, &[Vec3::X * one, Vec3::Y * one, Vec3::Z * one], 1e-6);
+    }
+
+    #[test]
+    fn ply_comments_can_mention_end_header() {
+        let text = "ply
+format ascii 1.0
+comment everything before end_header is the header
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0
+1 0 0
+0 1 0
+3 0 1 2
+";
+        let mesh = read_ply(text.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices, vec![Vec3::ZERO, Vec3::X, Vec3::Y]);
+        assert_eq!(mesh.faces, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn bad_indices_are_errors() {
+        let ply = |indices: &str| {
+            format!(
+                "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0
+1 0 0
+0 1 0
+3 {indices}
+"
+            )
+        };
+        assert!(read_ply(ply("0 1 2").as_bytes()).is_ok());
+        assert!(read_ply(ply("0 -1 2").as_bytes()).is_err());
+        assert!(read_ply(ply("0 1 3").as_bytes()).is_err());
+
+        let off = |indices: &str| format!("OFF\n3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 {indices}\n");
+        assert!(read_off(off("0 1 2").as_bytes()).is_ok());
+        assert!(read_off(off("0 -1 2").as_bytes()).is_err());
+        assert!(read_off(off("0 1 3").as_bytes()).is_err());
+    }
+}
@@ -4,4 +4,10 @@ pub(crate) mod polygon;
 pub(crate) mod triangle;
 pub(crate) mod intersection;
 pub(crate) mod accelerated_polygon;
+pub(crate) mod sdf;
+pub(crate) mod heightfield;
+pub(crate) mod volume;
+pub(crate) mod voxel_grid;
+pub(crate) mod mesh;
+pub(crate) mod mesh_io;
 mod aabb;
@@ -59,7 +59,8 @@ impl RenderIntersection for Plane {
         )
     }
 
-    fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3) {
-        todo!()
+    fn uv_derivatives(&self, _uv: Vec2) -> (Vec3, Vec3) {
+        let (x, y, _n) = build_orthonormal_basis(self.normal);
+        (x, y)
     }
 }
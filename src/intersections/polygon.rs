@@ -1,7 +1,10 @@
-use crate::intersections::intersection::{RenderIntersection, OBJECT_TOLERANCE};
+use crate::intersections::intersection::{RenderIntersection, SurfacePoint};
 use crate::*;
+use std::io;
 use std::path::Path;
 use tinystl::StlData;
+use crate::intersections::mesh::{IndexedMesh, MeshProcessing, MeshStats};
+use crate::intersections::mesh_io::{read_mesh, write_mesh};
 use crate::intersections::triangle::Triangle;
 
 #[derive(Debug)]
@@ -13,7 +16,7 @@ impl Polygon {
     pub fn new(triangles: Vec<[Vec3; 3]>) -> Polygon {
         let triangles = triangles
             .into_iter()
-            .map(|tri| Triangle::new(tri))
+            .map(Triangle::new)
             .collect();
         Self { triangles }
     }
@@ -36,8 +39,7 @@ impl Polygon {
         Self {
             triangles: polygons
                 .into_iter()
-                .map(|x| x.triangles)
-                .flatten()
+                .flat_map(|x| x.triangles)
                 .collect(),
         }
     }
@@ -55,7 +57,31 @@ impl Polygon {
         Self::new_from_vertices_and_indies(corners, vec![[0, 1, 2], [0, 2, 3]])
     }
 
-    pub fn stl_to_points(path: impl AsRef<Path>, scale: f32, offset: Vec3) -> Option<Vec<Triangle>> {
+    /// Closed axis aligned box, with outward facing triangles so it can bound a volume
+    pub fn new_cuboid(center: Vec3, half_size: Vec3) -> Polygon {
+        let corners = (0..8)
+            .map(|i| {
+                let sign = Vec3::new(
+                    if i & 1 == 0 { -1. } else { 1. },
+                    if i & 2 == 0 { -1. } else { 1. },
+                    if i & 4 == 0 { -1. } else { 1. },
+                );
+                center + sign * half_size
+            })
+            .collect();
+        let faces = vec![
+            [0, 2, 3], [0, 3, 1], // -z
+            [4, 5, 7], [4, 7, 6], // +z
+            [0, 1, 5], [0, 5, 4], // -y
+            [2, 6, 7], [2, 7, 3], // +y
+            [0, 4, 6], [0, 6, 2], // -x
+            [1, 3, 7], [1, 7, 5], // +x
+        ];
+        Self::new_from_vertices_and_indies(corners, faces)
+    }
+
+    /// The triangles of an STL as they are stored, without dropping anything
+    pub fn stl_to_raw_triangles(path: impl AsRef<Path>, scale: f32, offset: Vec3) -> Option<Vec<[Vec3; 3]>> {
         let data = StlData::read_from_file(path).ok()?;
 
         let f = |x| Vec3::from_array(x) * scale + offset;
@@ -63,45 +89,146 @@ impl Polygon {
             .triangles
             .into_iter()
             .map(|x| [f(x.v1), f(x.v2), f(x.v3)])
-            .map(|x| Triangle::new(x))
+            .collect::<Vec<_>>();
+        Some(triangles)
+    }
+
+    pub fn stl_to_points(path: impl AsRef<Path>, scale: f32, offset: Vec3) -> Option<Vec<Triangle>> {
+        let triangles = Self::stl_to_raw_triangles(path, scale, offset)?
+            .into_iter()
+            .map(Triangle::new)
             .filter(|x| x.normal_raw().length() * 0.5 > 0.01)
             .collect::<Vec<_>>();
         Some(triangles)
     }
 
+    /// Welds the unconnected triangles of an STL and gives them smooth vertex normals
+    pub fn stl_to_smooth_points(
+        path: impl AsRef<Path>,
+        scale: f32,
+        offset: Vec3,
+        settings: MeshProcessing,
+    ) -> Option<(Vec<Triangle>, MeshStats)> {
+        let triangles = Self::stl_to_raw_triangles(path, scale, offset)?;
+        let (mesh, stats) = IndexedMesh::weld(&triangles, settings);
+        Some((mesh.smooth_triangles(settings), stats))
+    }
+
+    /// Any mesh `read_mesh` understands (STL, PLY, OFF), keeping the file's vertex normals and colours
+    pub fn mesh_file_to_points(path: impl AsRef<Path>, scale: f32, offset: Vec3) -> io::Result<Vec<Triangle>> {
+        Ok(read_mesh(path)?.transformed(scale, offset).triangles())
+    }
+
+    pub fn new_from_mesh_file(path: impl AsRef<Path>, scale: f32, offset: Vec3) -> io::Result<Self> {
+        Ok(Self::from_triangles(Self::mesh_file_to_points(path, scale, offset)?))
+    }
+
+    /// Writes the triangles to an STL, OBJ or PLY file, picked by extension
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_mesh(path, &IndexedMesh::from_triangles(&self.triangles))
+    }
+
+    pub fn new_smoothed_from_stl(
+        path: impl AsRef<Path>,
+        scale: f32,
+        offset: Vec3,
+        settings: MeshProcessing,
+    ) -> Option<(Self, MeshStats)> {
+        let (triangles, stats) = Self::stl_to_smooth_points(path, scale, offset, settings)?;
+        Some((Self::from_triangles(triangles), stats))
+    }
+
     pub fn new_from_stl(path: impl AsRef<Path>, scale: f32, offset: Vec3) -> Option<Self> {
         let triangles = Self::stl_to_points(path, scale, offset)?;
         Some(Self::from_triangles(triangles))
     }
 }
 
+impl Polygon {
+    fn triangle_at(&self, point: Vec3) -> &Triangle {
+        self.triangles
+            .iter()
+            .find(|triangle| triangle.includes_point(point))
+            .or_else(|| {
+                self.triangles
+                    .iter()
+                    .filter_map(|triangle| Some((triangle, triangle.get_area_diff_point(point)?)))
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(triangle, _)| triangle)
+            })
+            .unwrap_or(&self.triangles[0])
+    }
+}
+
 impl RenderIntersection for Polygon {
     fn intersects(&self, ray: Ray) -> Vec<Vec3> {
         self.triangles
             .iter()
-            .map(|triangle| triangle.intersects(ray))
-            .flatten()
+            .flat_map(|triangle| triangle.intersects(ray))
             .collect()
     }
 
-    fn normal_at(&self, impact: Vec3) -> Vec3 {
+    /// Each hit is shaded by the triangle it's on, smooth normals and colours included
+    fn surface_hits(&self, ray: Ray) -> Vec<SurfacePoint> {
         self.triangles
             .iter()
-            .find(|triangle| triangle.includes_point(impact))
-            // TODO: FIX vvvvv - Seriously problematic
-            .unwrap_or(&self.triangles[0])
-            .normal_at(impact)
+            .flat_map(|triangle| triangle.surface_hits(ray))
+            .collect()
+    }
+
+    /// Only for points found some other way than `surface_hits`. Searches every triangle, and falls back
+    /// to the one whose plane is nearest when rounding puts the point just outside all of them.
+    fn normal_at(&self, impact: Vec3) -> Vec3 {
+        self.triangle_at(impact).normal_at(impact)
     }
 
     fn includes_point_on_surface(&self, point: Vec3) -> bool {
         self.triangles.iter().any(|x| x.includes_point(point))
     }
 
-    fn uv(&self, at: Vec3) -> Vec2 {
-        todo!()
+    fn to_mesh(&self) -> Option<IndexedMesh> {
+        Some(IndexedMesh::from_triangles(&self.triangles))
     }
 
-    fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3) {
-        todo!()
+    fn surface_colour(&self, at: Vec3) -> Option<Vec3Colour> {
+        // Don't go looking for the triangle unless the mesh was coloured in the first place
+        if !self.triangles.first()?.has_colours() {
+            return None;
+        }
+        self.triangle_at(at).surface_colour(at)
+    }
+
+    /// Meshes carry no texture coordinates, texture them with a `Projected` texture instead
+    fn uv(&self, _at: Vec3) -> Vec2 {
+        Vec2::ZERO
+    }
+
+    fn uv_derivatives(&self, _uv: Vec2) -> (Vec3, Vec3) {
+        (Vec3::X, Vec3::Y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_are_shaded_by_the_triangle_they_hit() {
+        // A square whose normals tilt towards +x on the right hand side
+        let corners = [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y];
+        let tilted = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let normals = [Vec3::Z, tilted, tilted, Vec3::Z];
+        let triangle = |face: [usize; 3]| Triangle::new_with_normals(face.map(|i| corners[i]), face.map(|i| normals[i]));
+        let polygon = Polygon::from_triangles(vec![triangle([0, 1, 2]), triangle([0, 2, 3])]);
+
+        // Points on either side of the diagonal, each blending the normals of its own triangle
+        for x in [0.25, 0.5, 0.75] {
+            let hits = polygon.surface_hits(Ray::new(Vec3::new(x, 0.3, 1.0), -Vec3::Z));
+            assert!(!hits.is_empty());
+            let expected = Vec3::Z.lerp(tilted, x).normalize();
+            for hit in hits {
+                assert!(hit.normal.abs_diff_eq(expected, 1e-4), "{} != {expected} at {x}", hit.normal);
+            }
+        }
     }
 }
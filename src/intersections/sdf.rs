@@ -0,0 +1,605 @@
+use crate::intersections::intersection::{RenderIntersection, OBJECT_TOLERANCE};
+use crate::*;
+use glam::Quat;
+use std::f32::consts::{PI, TAU};
+use std::fmt::Debug;
+
+/// Anything that can report its (signed) distance to a point.
+/// Negative values are inside the shape, positive values outside.
+/// Implementations should never overestimate the true distance or the marcher will step through the surface.
+pub trait SignedDistance: Debug + Sync {
+    fn distance(&self, point: Vec3) -> f32;
+}
+
+/// Renders any signed distance function with sphere tracing
+#[derive(Debug)]
+pub struct SdfObject {
+    sdf: Box<dyn SignedDistance>,
+    max_steps: u32,
+    max_distance: Length,
+    /// Fraction of the reported distance to actually step, values below 1 make up for
+    /// distance functions that overestimate (twists, displacements)
+    step_scale: f32,
+}
+
+impl SdfObject {
+    const MAX_STEPS: u32 = 256;
+    const MAX_DISTANCE: Length = 1000.0;
+    const NORMAL_EPSILON: f32 = 0.0005;
+    /// How far rays starting on the surface are lifted off it, a few times the hit band
+    const ESCAPE_DISTANCE: Length = OBJECT_TOLERANCE * 4.0;
+
+    pub fn new(sdf: impl SignedDistance + 'static) -> Self {
+        Self::new_with_control(sdf, Self::MAX_STEPS, Self::MAX_DISTANCE, 1.0)
+    }
+
+    pub fn new_with_control(
+        sdf: impl SignedDistance + 'static,
+        max_steps: u32,
+        max_distance: Length,
+        step_scale: f32,
+    ) -> Self {
+        Self {
+            sdf: Box::new(sdf),
+            max_steps,
+            max_distance,
+            step_scale,
+        }
+    }
+
+    fn distance(&self, point: Vec3) -> f32 {
+        self.sdf.distance(point)
+    }
+
+    /// Where the ray first meets the surface, if it does within the step and distance limits
+    fn march(&self, ray: Ray) -> Option<Vec3> {
+        // Rays that bounced off this surface start inside the hit band and would immediately hit where they
+        // started. Lift them a fixed step off the surface along the normal, to the side they're heading for.
+        let mut origin = ray.start();
+        if self.distance(origin).abs() < Self::ESCAPE_DISTANCE {
+            let normal = self.normal_at(origin);
+            let heading = if normal.dot(ray.direction()) >= 0.0 { 1.0 } else { -1.0 };
+            origin += normal * heading * Self::ESCAPE_DISTANCE;
+        }
+
+        // March on whichever side of the surface that leaves us on, so refracted rays find their way out
+        let side = self.distance(origin).signum();
+
+        let mut t = 0.0;
+        for _ in 0..self.max_steps {
+            let point = origin + ray.direction() * t;
+            let d = side * self.distance(point);
+            if d < OBJECT_TOLERANCE {
+                return Some(point);
+            }
+            t += d * self.step_scale;
+            if t > self.max_distance {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+impl RenderIntersection for SdfObject {
+    fn intersects(&self, ray: Ray) -> Vec<Vec3> {
+        self.march(ray).into_iter().collect()
+    }
+
+    /// Gradient of the distance field using the tetrahedron technique (4 samples instead of 6)
+    fn normal_at(&self, impact: Vec3) -> Vec3 {
+        let h = Self::NORMAL_EPSILON;
+        [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ]
+        .into_iter()
+        .map(|k| k * self.distance(impact + k * h))
+        .sum::<Vec3>()
+        .normalize()
+    }
+
+    fn includes_point_on_surface(&self, point: Vec3) -> bool {
+        self.distance(point).abs() <= OBJECT_TOLERANCE * 2.0
+    }
+
+    /// Spherical projection around the origin, there's no natural parameterisation of an arbitrary field
+    fn uv(&self, at: Vec3) -> Vec2 {
+        let [x, y, z] = at.normalize().to_array();
+        let phi = f32::atan2(y, x) + PI;
+        let theta = f32::acos(z.clamp(-1.0, 1.0));
+        Vec2::new(phi / TAU, theta / PI)
+    }
+
+    fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3) {
+        let phi = uv.x * TAU - PI;
+        let theta = uv.y * PI;
+        let du = TAU * Vec3::new(-phi.sin() * theta.sin(), phi.cos() * theta.sin(), 0.0);
+        let dv = PI * Vec3::new(phi.cos() * theta.cos(), phi.sin() * theta.cos(), -theta.sin());
+        (du, dv)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Primitives
+// https://iquilezles.org/articles/distfunctions/
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct SdfSphere {
+    pub centre: Vec3,
+    pub radius: Length,
+}
+
+impl SdfSphere {
+    pub fn new(centre: Vec3, radius: Length) -> Self {
+        Self { centre, radius }
+    }
+}
+
+impl SignedDistance for SdfSphere {
+    fn distance(&self, point: Vec3) -> f32 {
+        (point - self.centre).length() - self.radius
+    }
+}
+
+/// Axis aligned box, `half_size` is the distance from the centre to each face
+#[derive(Debug, Clone)]
+pub struct SdfBox {
+    pub centre: Vec3,
+    pub half_size: Vec3,
+    pub rounding: Length,
+}
+
+impl SdfBox {
+    pub fn new(centre: Vec3, half_size: Vec3) -> Self {
+        Self::new_rounded(centre, half_size, 0.0)
+    }
+
+    pub fn new_rounded(centre: Vec3, half_size: Vec3, rounding: Length) -> Self {
+        Self {
+            centre,
+            half_size,
+            rounding,
+        }
+    }
+}
+
+impl SignedDistance for SdfBox {
+    fn distance(&self, point: Vec3) -> f32 {
+        let q = (point - self.centre).abs() - self.half_size + self.rounding;
+        q.max(Vec3::ZERO).length() + q.max_element().min(0.0) - self.rounding
+    }
+}
+
+/// Torus lying in the xy plane (z is up)
+#[derive(Debug, Clone)]
+pub struct SdfTorus {
+    pub centre: Vec3,
+    pub major_radius: Length,
+    pub minor_radius: Length,
+}
+
+impl SdfTorus {
+    pub fn new(centre: Vec3, major_radius: Length, minor_radius: Length) -> Self {
+        Self {
+            centre,
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl SignedDistance for SdfTorus {
+    fn distance(&self, point: Vec3) -> f32 {
+        let p = point - self.centre;
+        let q = Vec2::new(p.truncate().length() - self.major_radius, p.z);
+        q.length() - self.minor_radius
+    }
+}
+
+/// Line segment from `a` to `b` inflated by `radius`
+#[derive(Debug, Clone)]
+pub struct SdfCapsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: Length,
+}
+
+impl SdfCapsule {
+    pub fn new(a: Vec3, b: Vec3, radius: Length) -> Self {
+        Self { a, b, radius }
+    }
+}
+
+impl SignedDistance for SdfCapsule {
+    fn distance(&self, point: Vec3) -> f32 {
+        let pa = point - self.a;
+        let ba = self.b - self.a;
+        // With both ends in the same place it's a sphere
+        let h = if ba.length_squared() > 0.0 {
+            (pa.dot(ba) / ba.length_squared()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (pa - ba * h).length() - self.radius
+    }
+}
+
+/// Capped cylinder standing along z
+#[derive(Debug, Clone)]
+pub struct SdfCylinder {
+    pub centre: Vec3,
+    pub radius: Length,
+    pub half_height: Length,
+}
+
+impl SdfCylinder {
+    pub fn new(centre: Vec3, radius: Length, half_height: Length) -> Self {
+        Self {
+            centre,
+            radius,
+            half_height,
+        }
+    }
+}
+
+impl SignedDistance for SdfCylinder {
+    fn distance(&self, point: Vec3) -> f32 {
+        let p = point - self.centre;
+        let d = Vec2::new(p.truncate().length(), p.z).abs() - Vec2::new(self.radius, self.half_height);
+        d.max_element().min(0.0) + d.max(Vec2::ZERO).length()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SdfPlane {
+    normal: Vec3,
+    centre: Vec3,
+}
+
+impl SdfPlane {
+    pub fn new(normal: Vec3, centre: Vec3) -> Self {
+        Self {
+            normal: normal.normalize(),
+            centre,
+        }
+    }
+}
+
+impl SignedDistance for SdfPlane {
+    fn distance(&self, point: Vec3) -> f32 {
+        (point - self.centre).dot(self.normal)
+    }
+}
+
+/// Power-8 Mandelbulb using the usual distance estimator
+/// https://iquilezles.org/articles/mandelbulb/
+#[derive(Debug, Clone)]
+pub struct Mandelbulb {
+    pub centre: Vec3,
+    pub scale: f32,
+    pub power: f32,
+    pub iterations: u32,
+}
+
+impl Mandelbulb {
+    pub fn new(centre: Vec3, scale: f32) -> Self {
+        Self::new_with_control(centre, scale, 8.0, 12)
+    }
+
+    pub fn new_with_control(centre: Vec3, scale: f32, power: f32, iterations: u32) -> Self {
+        Self {
+            centre,
+            scale,
+            power,
+            iterations,
+        }
+    }
+}
+
+impl SignedDistance for Mandelbulb {
+    fn distance(&self, point: Vec3) -> f32 {
+        let c = (point - self.centre) / self.scale;
+        let mut z = c;
+        let mut dr = 1.0;
+        let mut r = z.length();
+
+        for _ in 0..self.iterations {
+            // At the centre the angles are undefined, and it stays there
+            if !(f32::EPSILON..=2.0).contains(&r) {
+                break;
+            }
+            let theta = (z.z / r).clamp(-1.0, 1.0).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+
+            let zr = r.powf(self.power);
+            z = zr * Vec3::new(theta.sin() * phi.cos(), phi.sin() * theta.sin(), theta.cos()) + c;
+            r = z.length();
+        }
+
+        if r < f32::EPSILON {
+            // r ln r goes to 0 with r
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr * self.scale
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Combinators
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct Union {
+    a: Box<dyn SignedDistance>,
+    b: Box<dyn SignedDistance>,
+}
+
+impl Union {
+    pub fn new(a: impl SignedDistance + 'static, b: impl SignedDistance + 'static) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+impl SignedDistance for Union {
+    fn distance(&self, point: Vec3) -> f32 {
+        self.a.distance(point).min(self.b.distance(point))
+    }
+}
+
+#[derive(Debug)]
+pub struct Intersection {
+    a: Box<dyn SignedDistance>,
+    b: Box<dyn SignedDistance>,
+}
+
+impl Intersection {
+    pub fn new(a: impl SignedDistance + 'static, b: impl SignedDistance + 'static) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+impl SignedDistance for Intersection {
+    fn distance(&self, point: Vec3) -> f32 {
+        self.a.distance(point).max(self.b.distance(point))
+    }
+}
+
+/// `a` with `b` carved out of it
+#[derive(Debug)]
+pub struct Subtraction {
+    a: Box<dyn SignedDistance>,
+    b: Box<dyn SignedDistance>,
+}
+
+impl Subtraction {
+    pub fn new(a: impl SignedDistance + 'static, b: impl SignedDistance + 'static) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+impl SignedDistance for Subtraction {
+    fn distance(&self, point: Vec3) -> f32 {
+        self.a.distance(point).max(-self.b.distance(point))
+    }
+}
+
+/// Union that blends the two shapes together over roughly `smoothness` units
+#[derive(Debug)]
+pub struct SmoothUnion {
+    a: Box<dyn SignedDistance>,
+    b: Box<dyn SignedDistance>,
+    smoothness: f32,
+}
+
+impl SmoothUnion {
+    pub fn new(
+        a: impl SignedDistance + 'static,
+        b: impl SignedDistance + 'static,
+        smoothness: f32,
+    ) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+            smoothness,
+        }
+    }
+}
+
+impl SignedDistance for SmoothUnion {
+    fn distance(&self, point: Vec3) -> f32 {
+        let (d1, d2) = (self.a.distance(point), self.b.distance(point));
+        let k = self.smoothness.max(f32::EPSILON);
+        let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0.0, 1.0);
+        d2 + (d1 - d2) * h - k * h * (1.0 - h)
+    }
+}
+
+/// Infinitely repeats the shape on a grid with the given spacing, an axis with spacing 0 is not repeated
+#[derive(Debug)]
+pub struct Repeat {
+    sdf: Box<dyn SignedDistance>,
+    spacing: Vec3,
+}
+
+impl Repeat {
+    pub fn new(sdf: impl SignedDistance + 'static, spacing: Vec3) -> Self {
+        Self {
+            sdf: Box::new(sdf),
+            spacing,
+        }
+    }
+}
+
+impl SignedDistance for Repeat {
+    fn distance(&self, point: Vec3) -> f32 {
+        let repeated = Vec3::select(
+            self.spacing.cmpgt(Vec3::ZERO),
+            point - self.spacing * (point / self.spacing).round(),
+            point,
+        );
+        self.sdf.distance(repeated)
+    }
+}
+
+/// Twists the shape around the z axis by `rate` radians per unit of height.
+/// This isn't distance preserving, so pair it with a `step_scale` below 1.
+#[derive(Debug)]
+pub struct Twist {
+    sdf: Box<dyn SignedDistance>,
+    rate: f32,
+}
+
+impl Twist {
+    pub fn new(sdf: impl SignedDistance + 'static, rate: f32) -> Self {
+        Self {
+            sdf: Box::new(sdf),
+            rate,
+        }
+    }
+}
+
+impl SignedDistance for Twist {
+    fn distance(&self, point: Vec3) -> f32 {
+        let rotation = Quat::from_rotation_z(self.rate * point.z);
+        self.sdf.distance(rotation * point)
+    }
+}
+
+/// Offsets the surface by an arbitrary function of position.
+/// This isn't distance preserving, so pair it with a `step_scale` below 1.
+#[derive(Debug)]
+pub struct Displace {
+    sdf: Box<dyn SignedDistance>,
+    displacement: fn(Vec3) -> f32,
+}
+
+impl Displace {
+    pub fn new(sdf: impl SignedDistance + 'static, displacement: fn(Vec3) -> f32) -> Self {
+        Self {
+            sdf: Box::new(sdf),
+            displacement,
+        }
+    }
+}
+
+impl SignedDistance for Displace {
+    fn distance(&self, point: Vec3) -> f32 {
+        self.sdf.distance(point) + (self.displacement)(point)
+    }
+}
+
+#[derive(Debug)]
+pub struct Translate {
+    sdf: Box<dyn SignedDistance>,
+    offset: Vec3,
+}
+
+impl Translate {
+    pub fn new(sdf: impl SignedDistance + 'static, offset: Vec3) -> Self {
+        Self {
+            sdf: Box::new(sdf),
+            offset,
+        }
+    }
+}
+
+impl SignedDistance for Translate {
+    fn distance(&self, point: Vec3) -> f32 {
+        self.sdf.distance(point - self.offset)
+    }
+}
+
+/// Uniform scale about the origin
+#[derive(Debug)]
+pub struct Scale {
+    sdf: Box<dyn SignedDistance>,
+    scale: f32,
+}
+
+impl Scale {
+    pub fn new(sdf: impl SignedDistance + 'static, scale: f32) -> Self {
+        Self {
+            sdf: Box::new(sdf),
+            scale,
+        }
+    }
+}
+
+impl SignedDistance for Scale {
+    fn distance(&self, point: Vec3) -> f32 {
+        self.sdf.distance(point / self.scale) * self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere() -> SdfObject {
+        SdfObject::new(SdfSphere::new(Vec3::ZERO, 1.0))
+    }
+
+    #[test]
+    fn ray_hits_a_sphere_at_its_surface() {
+        let hits = sphere().intersects(Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::Z));
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].distance(Vec3::new(0.0, 0.0, -1.0)) < OBJECT_TOLERANCE * 2.0, "{}", hits[0]);
+    }
+
+    #[test]
+    fn ray_misses_a_sphere() {
+        assert!(sphere().intersects(Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::Z)).is_empty());
+        // Heading away from it
+        assert!(sphere().intersects(Ray::new(Vec3::new(0.0, 0.0, -5.0), -Vec3::Z)).is_empty());
+    }
+
+    #[test]
+    fn ray_from_inside_finds_the_far_side() {
+        let hits = sphere().intersects(Ray::new(Vec3::new(0.0, 0.0, 0.5), Vec3::Z));
+        assert_eq!(hits.len(), 1);
+        assert!((hits[0].z - 1.0).abs() < OBJECT_TOLERANCE * 2.0, "{}", hits[0]);
+    }
+
+    #[test]
+    fn rays_leaving_the_surface_do_not_hit_where_they_started() {
+        let start = Vec3::new(0.0, 0.0, -1.0);
+        // Bouncing back out
+        assert!(sphere().intersects(Ray::new(start, -Vec3::Z)).is_empty());
+        // Refracting in, should come out the far side
+        let hits = sphere().intersects(Ray::new(start, Vec3::Z));
+        assert_eq!(hits.len(), 1);
+        assert!((hits[0].z - 1.0).abs() < OBJECT_TOLERANCE * 2.0, "{}", hits[0]);
+    }
+
+    #[test]
+    fn normal_is_the_gradient() {
+        let point = Vec3::new(1.0, 2.0, -2.0).normalize();
+        assert!(sphere().normal_at(point).abs_diff_eq(point, 1e-3));
+
+        let cube = SdfObject::new(SdfBox::new(Vec3::ZERO, Vec3::ONE));
+        assert!(cube.normal_at(Vec3::new(0.2, 1.0, -0.3)).abs_diff_eq(Vec3::Y, 1e-3));
+    }
+
+    #[test]
+    fn degenerate_shapes_have_finite_distances() {
+        let bulb = Mandelbulb::new(Vec3::ZERO, 1.0);
+        assert!(bulb.distance(Vec3::ZERO).is_finite());
+
+        let capsule = SdfCapsule::new(Vec3::ONE, Vec3::ONE, 0.5);
+        assert!((capsule.distance(Vec3::new(1.0, 1.0, 2.0)) - 0.5).abs() < 1e-6);
+    }
+}
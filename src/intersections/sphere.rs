@@ -62,9 +62,10 @@ impl RenderIntersection for Sphere {
 
     fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3) {
         let r = self.radius;
-        let [u, v] = uv.to_array();
-        let du = Vec3::new(-r * u.sin() * v.sin(), r * u.cos() * v.sin(), 0.0);
-        let dv = Vec3::new( r * u.cos() * v.cos(), r * u.sin() * v.cos(), -r * v.sin());
+        let phi = uv.x * TAU - PI;
+        let theta = uv.y * PI;
+        let du = r * TAU * Vec3::new(-phi.sin() * theta.sin(), phi.cos() * theta.sin(), 0.0);
+        let dv = r * PI * Vec3::new(phi.cos() * theta.cos(), phi.sin() * theta.cos(), -theta.sin());
 
         (du, dv)
     }
@@ -1,12 +1,14 @@
-use crate::utils::{bounce_across_normal, random_cosine_direction};
 use crate::*;
-use rand::random;
 use crate::intersections::intersection::{RenderIntersection, OBJECT_TOLERANCE};
-use crate::materials::material::RenderMaterial;
+use crate::intersections::mesh::IndexedMesh;
 
 #[derive(Debug)]
 pub struct Triangle {
     vertices: [Vec3; 3],
+    /// Per vertex normals to interpolate between, for meshes approximating a smooth surface
+    normals: Option<[Vec3; 3]>,
+    /// Per vertex colours, from scanned meshes
+    colours: Option<[Vec3Colour; 3]>,
 }
 
 impl Triangle {
@@ -14,9 +16,38 @@ impl Triangle {
         // Assume triangles are not degenerate, if they are, the normal() method below will panic
         Self {
             vertices,
+            normals: None,
+            colours: None,
         }
     }
 
+    pub fn new_with_normals(vertices: [Vec3; 3], normals: [Vec3; 3]) -> Self {
+        Self {
+            vertices,
+            normals: Some(normals),
+            colours: None,
+        }
+    }
+
+    pub fn with_colours(self, colours: [Vec3Colour; 3]) -> Self {
+        Self {
+            colours: Some(colours),
+            ..self
+        }
+    }
+
+    pub(crate) fn has_colours(&self) -> bool {
+        self.colours.is_some()
+    }
+
+    pub(crate) fn normals(&self) -> Option<[Vec3; 3]> {
+        self.normals
+    }
+
+    pub(crate) fn colours(&self) -> Option<[Vec3Colour; 3]> {
+        self.colours
+    }
+
     pub(crate) fn normal_raw(&self) -> Vec3 {
         let [a, b, c] = self.vertices;
         let ab = b - a;
@@ -49,7 +80,7 @@ impl Triangle {
         let inv_det = 1.0 / det;
         let s = origin - tri_a;
         let u = inv_det * s.dot(ray_cross_e2);
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return None;
         }
 
@@ -103,6 +134,16 @@ impl Triangle {
         Some((area_sum - area_abc).abs())
     }
 
+    /// Weights of each vertex for a point on the triangle
+    pub fn barycentric(&self, point: Vec3) -> Vec3 {
+        let [a, b, c] = self.vertices;
+        let normal = self.normal_raw();
+        let area = normal.length_squared();
+        let u = (c - b).cross(point - b).dot(normal) / area;
+        let v = (a - c).cross(point - c).dot(normal) / area;
+        Vec3::new(u, v, 1.0 - u - v)
+    }
+
     pub fn includes_point(&self, point: Vec3) -> bool {
         self.get_area_diff_point(point)
             .is_some_and(|x| x < OBJECT_TOLERANCE)
@@ -115,18 +156,37 @@ impl RenderIntersection for Triangle {
     }
 
     fn normal_at(&self, impact: Vec3) -> Vec3 {
-        self.normal()
+        match self.normals {
+            Some([na, nb, nc]) => {
+                let [u, v, w] = self.barycentric(impact).to_array();
+                (na * u + nb * v + nc * w).normalize()
+            }
+            None => self.normal(),
+        }
     }
 
     fn includes_point_on_surface(&self, point: Vec3) -> bool {
         self.includes_point(point)
     }
 
+    fn to_mesh(&self) -> Option<IndexedMesh> {
+        Some(IndexedMesh::from_triangles(std::slice::from_ref(self)))
+    }
+
+    fn surface_colour(&self, at: Vec3) -> Option<Vec3Colour> {
+        let [ca, cb, cc] = self.colours?;
+        let [u, v, w] = self.barycentric(at).to_array();
+        Some(ca * u + cb * v + cc * w)
+    }
+
+    /// The barycentric weights of the second and third corners, so the first corner is (0, 0)
     fn uv(&self, at: Vec3) -> Vec2 {
-        todo!()
+        let [_, v, w] = self.barycentric(at).to_array();
+        Vec2::new(v, w)
     }
 
-    fn uv_derivatives(&self, uv: Vec2) -> (Vec3, Vec3) {
-        todo!()
+    fn uv_derivatives(&self, _uv: Vec2) -> (Vec3, Vec3) {
+        let [a, b, c] = self.vertices();
+        (b - a, c - a)
     }
 }
\ No newline at end of file
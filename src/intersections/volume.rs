@@ -0,0 +1,208 @@
+use crate::intersections::aabb::AABB;
+use crate::intersections::intersection::{RenderIntersection, OBJECT_TOLERANCE};
+use crate::intersections::voxel_grid::VoxelGrid;
+use crate::utils::sample_free_flight;
+use crate::*;
+use rand::random;
+use std::sync::Arc;
+
+/// A constant density medium filling a closed boundary.
+/// The boundary itself is invisible, rays only "hit" the volume where they are scattered inside it,
+/// the material decides what happens there (usually a `Medium`).
+#[derive(Debug)]
+pub struct Volume {
+    boundary: Box<dyn RenderIntersection>,
+    extinction: f32,
+}
+
+impl Volume {
+    pub fn new(boundary: impl RenderIntersection + 'static, extinction: f32) -> Self {
+        Self {
+            boundary: Box::new(boundary),
+            extinction,
+        }
+    }
+
+    pub fn boxed_new(boundary: Box<dyn RenderIntersection>, extinction: f32) -> Self {
+        Self {
+            boundary,
+            extinction,
+        }
+    }
+
+    /// The stretches of the ray that are inside the boundary, as (enter, leave) distances.
+    /// Relies on the boundary reporting every crossing, so an odd number means the ray starts inside.
+    fn inside_intervals(&self, ray: Ray) -> Vec<(Length, Length)> {
+        let mut crossings = self
+            .boundary
+            .intersects(ray)
+            .into_iter()
+            .map(|point| point.distance(ray.start()))
+            .collect::<Vec<_>>();
+        crossings.sort_by(|a, b| a.total_cmp(b));
+        // A ray through a shared edge of a mesh hits both triangles, that's still only one crossing
+        crossings.dedup_by(|a, b| (*a - *b).abs() < OBJECT_TOLERANCE);
+
+        if crossings.len() % 2 == 1 {
+            crossings.insert(0, 0.0);
+        }
+        crossings.chunks_exact(2).map(|x| (x[0], x[1])).collect()
+    }
+}
+
+impl RenderIntersection for Volume {
+    fn intersects(&self, ray: Ray) -> Vec<Vec3> {
+        // Spend a single free-flight distance across all the stretches inside the boundary
+        let mut remaining = sample_free_flight(self.extinction);
+        for (enter, leave) in self.inside_intervals(ray) {
+            if remaining < leave - enter {
+                return vec![ray.pos_at_length(enter + remaining)];
+            }
+            remaining -= leave - enter;
+        }
+        vec![]
+    }
+
+    /// Media have no surface so there's no meaningful normal, it just needs to be a unit vector
+    fn normal_at(&self, _impact: Vec3) -> Vec3 {
+        Vec3::Z
+    }
+
+    fn includes_point_on_surface(&self, _point: Vec3) -> bool {
+        false
+    }
+
+    fn uv(&self, _at: Vec3) -> Vec2 {
+        Vec2::ZERO
+    }
+
+    fn uv_derivatives(&self, _uv: Vec2) -> (Vec3, Vec3) {
+        (Vec3::X, Vec3::Y)
+    }
+}
+
+/// A medium whose density varies through space, read from a voxel grid.
+/// Collisions are sampled with delta tracking and transmittance estimated with ratio tracking, both against
+/// the grid's maximum density, which keeps them unbiased however the density varies.
+#[derive(Debug)]
+pub struct GridVolume {
+    density: Arc<VoxelGrid>,
+    bounds: AABB,
+    /// Extinction coefficient at a density of 1
+    extinction: f32,
+    /// Upper bound of the extinction anywhere in the grid
+    majorant: f32,
+}
+
+impl GridVolume {
+    pub fn new(density: Arc<VoxelGrid>, extinction: f32) -> Self {
+        let bounds = AABB::from_min_max(density.min(), density.max());
+        let majorant = density.max_value() * extinction;
+        Self {
+            density,
+            bounds,
+            extinction,
+            majorant,
+        }
+    }
+
+    fn extinction_at(&self, point: Vec3) -> f32 {
+        self.density.sample_scalar(point) * self.extinction
+    }
+
+    /// Fraction of light that makes it along the ray up to `max_distance`, estimated with ratio tracking.
+    /// Unbiased, but noisy for very thin media, which is the price of not marching the whole grid.
+    pub fn transmittance(&self, ray: Ray, max_distance: Length) -> f32 {
+        let Some((enter, leave)) = self.bounds.intersection_range(ray) else {
+            return 1.0;
+        };
+        let leave = leave.min(max_distance);
+
+        // Every tentative collision at the majorant keeps the fraction of it that's a null collision
+        let mut transmittance = 1.0;
+        let mut t = enter;
+        loop {
+            t += sample_free_flight(self.majorant);
+            if t >= leave {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.extinction_at(ray.pos_at_length(t)) / self.majorant;
+        }
+    }
+}
+
+impl RenderIntersection for GridVolume {
+    fn intersects(&self, ray: Ray) -> Vec<Vec3> {
+        let Some((enter, leave)) = self.bounds.intersection_range(ray) else {
+            return vec![];
+        };
+
+        // Delta tracking: take tentative steps through a fictitious homogeneous medium at the majorant
+        // and accept each one with probability real / majorant, the rest are null collisions
+        let mut t = enter;
+        loop {
+            t += sample_free_flight(self.majorant);
+            if t >= leave {
+                return vec![];
+            }
+            let point = ray.pos_at_length(t);
+            if random::<f32>() * self.majorant < self.extinction_at(point) {
+                return vec![point];
+            }
+        }
+    }
+
+    fn normal_at(&self, _impact: Vec3) -> Vec3 {
+        Vec3::Z
+    }
+
+    fn includes_point_on_surface(&self, _point: Vec3) -> bool {
+        false
+    }
+
+    fn uv(&self, _at: Vec3) -> Vec2 {
+        Vec2::ZERO
+    }
+
+    fn uv_derivatives(&self, _uv: Vec2) -> (Vec3, Vec3) {
+        (Vec3::X, Vec3::Y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::UVec3;
+
+    /// A 2x2x2 unit box of `density`, except for a column twice as dense at high x and y. Rays near x = y = 0
+    /// only see `density`, with the majorant twice the extinction they go through.
+    fn grid(density: f32, extinction: f32) -> GridVolume {
+        let mut data = vec![density; 8];
+        data[3] = density * 2.0;
+        data[7] = density * 2.0;
+        let grid = VoxelGrid::new(UVec3::splat(2), 1, data, Vec3::ZERO, Vec3::ONE).unwrap();
+        GridVolume::new(Arc::new(grid), extinction)
+    }
+
+    #[test]
+    fn ratio_tracking_matches_beer_lambert() {
+        const SAMPLES: usize = 100_000;
+        let volume = grid(0.5, 3.0);
+        let ray = Ray::new(Vec3::new(0.1, 0.1, -1.0), Vec3::Z);
+        for max_distance in [1.25, 1.5, 10.0] {
+            let estimate = (0..SAMPLES)
+                .map(|_| volume.transmittance(ray, max_distance))
+                .sum::<f32>()
+                / SAMPLES as f32;
+            let inside = (max_distance - 1.0).min(1.0);
+            let expected = (-1.5 * inside).exp();
+            assert!((estimate - expected).abs() < 0.01, "{estimate} != {expected} over {inside}");
+        }
+    }
+
+    #[test]
+    fn rays_missing_the_grid_are_not_attenuated() {
+        let volume = grid(0.5, 3.0);
+        assert_eq!(volume.transmittance(Ray::new(Vec3::new(2.0, 0.5, -1.0), Vec3::Z), 10.0), 1.0);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::utils::invalid_data;
+use crate::*;
+use glam::{IVec3, UVec3};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Dense 3D grid of samples (density, or RGB for emission) placed in a world space box.
+/// Samples sit at the centres of the voxels and are trilinearly interpolated between.
+#[derive(Debug)]
+pub struct VoxelGrid {
+    resolution: UVec3,
+    channels: usize,
+    /// x fastest, then y, then z, with the channels of a voxel stored together
+    data: Vec<f32>,
+    min: Vec3,
+    max: Vec3,
+}
+
+/// How many samples a grid holds, for grids of 1 or 3 channels that have at least one voxel and fit in memory
+fn sample_count(resolution: UVec3, channels: usize) -> io::Result<usize> {
+    if channels != 1 && channels != 3 {
+        return Err(invalid_data(format!("voxels need 1 or 3 channels, not {channels}")));
+    }
+    if resolution.min_element() == 0 {
+        return Err(invalid_data(format!("voxel grid sizes must be at least 1, not {resolution}")));
+    }
+    resolution
+        .to_array()
+        .iter()
+        .try_fold(channels, |count, size| count.checked_mul(*size as usize))
+        .ok_or_else(|| invalid_data(format!("voxel grid of {resolution} is too big")))
+}
+
+impl VoxelGrid {
+    /// `data` needs exactly one sample per channel per voxel
+    pub fn new(resolution: UVec3, channels: usize, data: Vec<f32>, min: Vec3, max: Vec3) -> io::Result<Self> {
+        let count = sample_count(resolution, channels)?;
+        if data.len() != count {
+            return Err(invalid_data(format!(
+                "voxel grid of {resolution} with {channels} channels needs {count} samples, not {}",
+                data.len()
+            )));
+        }
+        Ok(Self {
+            resolution,
+            channels,
+            data,
+            min,
+            max,
+        })
+    }
+
+    /// Headerless little endian f32 samples
+    pub fn load_raw(
+        path: impl AsRef<Path>,
+        resolution: UVec3,
+        channels: usize,
+        min: Vec3,
+        max: Vec3,
+    ) -> io::Result<Self> {
+        let count = sample_count(resolution, channels)?;
+        let bytes = std::fs::read(path)?;
+        let data = SampleType::F32.decode(&bytes, count, false)?;
+        Self::new(resolution, channels, data, min, max)
+    }
+
+    /// Reads the common subset of NRRD: raw encoding, 3D scalar or 4D with 3 leading channels,
+    /// float/double or unsigned integer samples (integers are normalised to [0, 1]),
+    /// and the data either attached after the header or in a separate `data file`.
+    /// https://teem.sourceforge.net/nrrd/format.html
+    pub fn load_nrrd(path: impl AsRef<Path>, min: Vec3, max: Vec3) -> io::Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+
+        if !bytes.starts_with(b"NRRD") {
+            return Err(invalid_data("missing NRRD magic"));
+        }
+        // The header ends at a blank line, with either Unix or Windows line endings
+        let blank_line = |terminator: &'static [u8]| {
+            let end = bytes.windows(terminator.len()).position(|x| x == terminator)?;
+            Some((end, end + terminator.len()))
+        };
+        let (header_end, data_start) = [blank_line(b"\n\n"), blank_line(b"\r\n\r\n")]
+            .into_iter()
+            .flatten()
+            .min()
+            .ok_or_else(|| invalid_data("unterminated NRRD header"))?;
+        let header = String::from_utf8_lossy(&bytes[..header_end]);
+
+        let fields = header
+            .lines()
+            .skip(1)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_lowercase(), value.trim_start_matches('=').trim().to_string()))
+            .collect::<HashMap<_, _>>();
+        let field = |key: &str| {
+            fields
+                .get(key)
+                .ok_or_else(|| invalid_data(format!("NRRD header has no `{key}` field")))
+        };
+
+        let encoding = field("encoding")?;
+        if encoding != "raw" {
+            return Err(invalid_data(format!("unsupported NRRD encoding `{encoding}`")));
+        }
+
+        let sizes = field("sizes")?
+            .split_whitespace()
+            .map(|x| x.parse::<u32>().map_err(|e| invalid_data(e.to_string())))
+            .collect::<io::Result<Vec<_>>>()?;
+        let (channels, resolution) = match sizes.as_slice() {
+            [x, y, z] => (1, UVec3::new(*x, *y, *z)),
+            [c @ (1 | 3), x, y, z] => (*c as usize, UVec3::new(*x, *y, *z)),
+            _ => return Err(invalid_data(format!("unsupported NRRD sizes {sizes:?}"))),
+        };
+
+        let sample_type = SampleType::from_nrrd(field("type")?)?;
+        let big_endian = fields.get("endian").is_some_and(|x| x == "big");
+
+        let count = sample_count(resolution, channels)?;
+        let data = match fields.get("data file") {
+            Some(file) => std::fs::read(path.with_file_name(file))?,
+            None => bytes[data_start..].to_vec(),
+        };
+
+        let data = sample_type.decode(&data, count, big_endian)?;
+        Self::new(resolution, channels, data, min, max)
+    }
+
+    pub fn min(&self) -> Vec3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Vec3 {
+        self.max
+    }
+
+    pub fn max_value(&self) -> f32 {
+        self.data.iter().copied().fold(0.0, f32::max)
+    }
+
+    fn voxel(&self, at: IVec3) -> &[f32] {
+        let at = at.clamp(IVec3::ZERO, self.resolution.as_ivec3() - 1).as_uvec3();
+        let index = ((at.z * self.resolution.y + at.y) * self.resolution.x + at.x) as usize;
+        &self.data[index * self.channels..(index + 1) * self.channels]
+    }
+
+    /// Trilinear interpolation of every channel at a world position, zero outside the box
+    fn sample(&self, point: Vec3) -> [f32; 3] {
+        let local = (point - self.min) / (self.max - self.min);
+        if local.cmplt(Vec3::ZERO).any() || local.cmpgt(Vec3::ONE).any() {
+            return [0.0; 3];
+        }
+
+        let grid = local * self.resolution.as_vec3() - 0.5;
+        let base = grid.floor();
+        let t = grid - base;
+        let base = base.as_ivec3();
+
+        let mut result = [0.0; 3];
+        for corner in 0..8 {
+            let offset = IVec3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = Vec3::select(offset.cmpeq(IVec3::ONE), t, 1.0 - t).element_product();
+            for (channel, value) in self.voxel(base + offset).iter().enumerate() {
+                result[channel] += weight * value;
+            }
+        }
+        result
+    }
+
+    pub fn sample_scalar(&self, point: Vec3) -> f32 {
+        self.sample(point)[0]
+    }
+
+    /// Single channel grids are treated as grey
+    pub fn sample_colour(&self, point: Vec3) -> Vec3Colour {
+        let [r, g, b] = self.sample(point);
+        if self.channels == 1 {
+            Vec3::splat(r)
+        } else {
+            Vec3::new(r, g, b)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SampleType {
+    U8,
+    U16,
+    F32,
+    F64,
+}
+
+impl SampleType {
+    fn from_nrrd(name: &str) -> io::Result<Self> {
+        match name {
+            "uchar" | "unsigned char" | "uint8" | "uint8_t" => Ok(Self::U8),
+            "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => Ok(Self::U16),
+            "float" => Ok(Self::F32),
+            "double" => Ok(Self::F64),
+            _ => Err(invalid_data(format!("unsupported NRRD type `{name}`"))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::U16 => 2,
+            Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], count: usize, big_endian: bool) -> io::Result<Vec<f32>> {
+        let needed = count.checked_mul(self.size());
+        if needed.is_none_or(|needed| bytes.len() < needed) {
+            return Err(invalid_data(format!(
+                "expected {count} samples of {} bytes of voxel data, found {} bytes",
+                self.size(),
+                bytes.len()
+            )));
+        }
+        let samples = bytes.chunks_exact(self.size()).take(count).map(|x| {
+            let mut x = x.to_vec();
+            if big_endian {
+                x.reverse();
+            }
+            match self {
+                Self::U8 => x[0] as f32 / u8::MAX as f32,
+                Self::U16 => u16::from_le_bytes([x[0], x[1]]) as f32 / u16::MAX as f32,
+                Self::F32 => f32::from_le_bytes([x[0], x[1], x[2], x[3]]),
+                Self::F64 => f64::from_le_bytes([x[0], x[1], x[2], x[3], x[4], x[5], x[6], x[7]]) as f32,
+            }
+        });
+        Ok(samples.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A file of the test's own in the temp directory, so tests running at once don't share
+    fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ray_voxel_grid_{}_{name}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    fn load(name: &str, header: &str, data: &[u8]) -> io::Result<VoxelGrid> {
+        let path = temp_file(name, &[header.as_bytes(), data].concat());
+        let grid = VoxelGrid::load_nrrd(&path, Vec3::ZERO, Vec3::ONE);
+        std::fs::remove_file(path).unwrap();
+        grid
+    }
+
+    const VALUES: [f32; 8] = [0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7];
+
+    #[test]
+    fn nrrd_with_attached_data() {
+        let header = "NRRD0004\n# a comment\ntype: float\ndimension: 3\nsizes: 2 2 2\nendian: little\nencoding: raw\n\n";
+        let grid = load("attached.nrrd", header, &floats(&VALUES)).unwrap();
+        assert_eq!(grid.data, VALUES);
+        // The voxel at x = 1, y = 0, z = 1 has its centre three quarters of the way along x and z
+        assert!((grid.sample_scalar(Vec3::new(0.75, 0.25, 0.75)) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nrrd_with_crlf_header() {
+        let header = "NRRD0004\r\ntype: uchar\r\ndimension: 3\r\nsizes: 2 2 2\r\nencoding: raw\r\n\r\n";
+        let grid = load("crlf.nrrd", header, &[0, 51, 102, 153, 204, 255, 0, 0]).unwrap();
+        assert_eq!(grid.data[..6], [0.0, 0.2, 0.4, 0.6, 0.8, 1.0]);
+    }
+
+    #[test]
+    fn nrrd_with_separate_data_file() {
+        let data = temp_file("detached.raw", &floats(&VALUES));
+        let file_name = data.file_name().unwrap().to_str().unwrap();
+        let header = format!("NRRD0004\ntype: float\nsizes: 2 2 2\nencoding: raw\ndata file: {file_name}\n\n");
+        let grid = load("detached.nhdr", &header, &[]).unwrap();
+        std::fs::remove_file(data).unwrap();
+        assert_eq!(grid.data, VALUES);
+    }
+
+    #[test]
+    fn nrrd_with_rgb_samples() {
+        let header = "NRRD0004\ntype: float\ndimension: 4\nsizes: 3 1 1 2\nendian: big\nencoding: raw\n\n";
+        let data = [1.0f32, 0.5, 0.25, 0.0, 0.0, 1.0]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<_>>();
+        let grid = load("rgb.nrrd", header, &data).unwrap();
+        assert_eq!(grid.channels, 3);
+        assert_eq!(grid.resolution, UVec3::new(1, 1, 2));
+        assert_eq!(grid.sample_colour(Vec3::new(0.5, 0.5, 0.25)), Vec3::new(1.0, 0.5, 0.25));
+        assert_eq!(grid.sample_colour(Vec3::new(0.5, 0.5, 0.75)), Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn zero_and_huge_sizes_are_errors() {
+        let header = "NRRD0004\ntype: float\nsizes: 2 0 2\nencoding: raw\n\n";
+        assert_eq!(load("zero.nrrd", header, &[]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let header = "NRRD0004\ntype: float\nsizes: 3 4294967295 4294967295 4294967295\nencoding: raw\n\n";
+        assert_eq!(load("huge.nrrd", header, &[]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let path = temp_file("zero.raw", &floats(&VALUES));
+        let grid = VoxelGrid::load_raw(&path, UVec3::new(0, 2, 2), 1, Vec3::ZERO, Vec3::ONE);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(grid.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn raw_samples() {
+        let path = temp_file("samples.raw", &floats(&VALUES));
+        let grid = VoxelGrid::load_raw(&path, UVec3::new(2, 2, 2), 1, Vec3::ZERO, Vec3::ONE);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(grid.unwrap().data, VALUES);
+    }
+
+    #[test]
+    fn new_checks_its_samples() {
+        let new = |resolution: UVec3, channels: usize, samples: usize| {
+            VoxelGrid::new(resolution, channels, vec![0.0; samples], Vec3::ZERO, Vec3::ONE)
+        };
+        assert!(new(UVec3::new(2, 2, 2), 1, 8).is_ok());
+        assert!(new(UVec3::new(2, 2, 2), 3, 24).is_ok());
+        assert!(new(UVec3::new(2, 2, 2), 1, 7).is_err());
+        assert!(new(UVec3::new(2, 2, 2), 2, 16).is_err());
+        assert!(new(UVec3::new(2, 0, 2), 1, 0).is_err());
+    }
+}
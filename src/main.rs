@@ -6,13 +6,14 @@ mod objects;
 mod ray;
 mod renderer;
 mod scene;
+mod spectrum;
 pub mod utils;
 
 use crate::intersections::accelerated_polygon::AcceleratedPolygon;
 use crate::intersections::polygon::Polygon;
 use crate::intersections::sphere::Sphere;
 use crate::intersections::triangle::Triangle;
-use crate::materials::texture::Texture;
+use crate::materials::textured_mirror::TexturedMirror;
 use crate::objects::RenderObject;
 use crate::utils::ColourChange;
 pub use crate::{camera::*, ray::*, renderer::render2, scene::*};
@@ -69,12 +70,12 @@ fn coloured_spheres() -> Scene {
     let loc = Vec3::new(0.0, 2.0, 0.0);
     let camera = Camera::new_with_control(loc, Vec3::new(0.0, -1.0, 0.0), 75., 5, 5);
 
-    let material_center = Texture::new(
-        "assets/8k_earth_day_map.jpg",
-        Some("assets/8k_earth_normal_map.jpg"),
+    let material_center = TexturedMirror::new(
+        "assets/earthmap.jpg",
+        None::<&str>,
         Vec2::splat(1.0),
         0.0
-    );
+    ).unwrap();
 
     let objects = vec![RenderObject::new(
         Sphere::new(Vec3::new(0., 0., 0.), 0.8),
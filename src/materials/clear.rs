@@ -1,67 +1,282 @@
-use glam::Vec3;
-use rand::random;
+use glam::{Vec2, Vec3};
 use crate::hit::Hit;
 use crate::materials::material::RenderMaterial;
-use crate::{utils, Ray, Vec3Colour};
-use crate::utils::{bounce_across_normal, reflectance};
+use crate::materials::microfacet::{
+    roughness_to_alpha, sample_rough_dielectric, sample_rough_dielectric_with, ShadingFrame, SPECULAR_ALPHA,
+};
+use crate::materials::texture::Texture;
+use crate::materials::thin_film::ThinFilm;
+use crate::spectrum::{self, Dispersion};
+use crate::{MediumEntry, Ray, Vec3Colour};
+use std::sync::Arc;
 
-#[derive(Debug)]
-pub(crate) struct Clear {
+/// Glass, water and other dielectrics. Rough ones are frosted, scattering light through a GGX microsurface.
+///
+/// Rays keep track of which of these they're inside, so objects can sit inside each other (ice in water
+/// in a glass) and each boundary bends light by the ratio of the refractive indices on either side.
+/// Where objects overlap, the one with the higher priority wins and the other's surface is ignored there.
+#[derive(Debug, Clone)]
+pub struct Clear {
+    /// Fraction of each channel left after travelling one unit through the inside, so thicker glass is darker
     colour: Vec3Colour,
     refractive_index: f32,
-    roughness: f32
+    roughness: f32,
+    priority: u32,
+    /// Wavelength dependent refractive index, only used in spectral mode
+    dispersion: Option<Dispersion>,
+    thin_film: Option<ThinFilm>,
+    /// Colours light passing through the surface, like stained glass
+    tint: Option<Arc<dyn Texture>>,
 }
 
 impl Clear {
-    fn new(colour: Vec3Colour, refractive_index: f32, roughness: f32) -> Self {
+    pub const fn new(colour: Vec3Colour, refractive_index: f32, roughness: f32) -> Self {
         Self {
             colour,
             refractive_index,
             roughness,
+            priority: 0,
+            dispersion: None,
+            thin_film: None,
+            tint: None,
         }
     }
-    pub(crate) const GLASS: Self = Self {
+
+    /// Coats the surface in a thin film, e.g. soap or an anti-reflective coating
+    pub fn with_thin_film(self, thin_film: ThinFilm) -> Self {
+        Self {
+            thin_film: Some(thin_film),
+            ..self
+        }
+    }
+
+    /// n = a + b / λ², with λ in micrometres
+    pub fn with_cauchy(self, a: f32, b: f32) -> Self {
+        Self {
+            dispersion: Some(Dispersion::Cauchy { a, b }),
+            ..self
+        }
+    }
+
+    /// n² = 1 + Σ bᵢ λ² / (λ² - cᵢ), with λ in micrometres
+    pub fn with_sellmeier(self, b: [f32; 3], c: [f32; 3]) -> Self {
+        Self {
+            dispersion: Some(Dispersion::Sellmeier { b, c }),
+            ..self
+        }
+    }
+
+    pub fn with_tint(self, tint: impl Texture + 'static) -> Self {
+        Self {
+            tint: Some(Arc::new(tint)),
+            ..self
+        }
+    }
+
+    pub fn with_priority(self, priority: u32) -> Self {
+        Self { priority, ..self }
+    }
+    pub const GLASS: Self = Self {
         colour: Vec3::new(1.0, 1.0, 1.0),
         refractive_index: 1.5,
         roughness: 0.0,
+        priority: 0,
+        dispersion: None,
+        thin_film: None,
+        tint: None,
+    };
+    pub const WATER: Self = Self {
+        colour: Vec3::new(0.97, 0.99, 1.0),
+        refractive_index: 1.333,
+        roughness: 0.0,
+        priority: 0,
+        dispersion: None,
+        thin_film: None,
+        tint: None,
+    };
+    /// Wins over water where an ice cube pokes into it
+    pub const ICE: Self = Self {
+        colour: Vec3::new(1.0, 1.0, 1.0),
+        refractive_index: 1.31,
+        roughness: 0.0,
+        priority: 1,
+        dispersion: None,
+        thin_film: None,
+        tint: None,
     };
-    pub(crate) const INV_GLASS: Self = Self {
+    /// A film of soap with nothing but air either side of it
+    pub const SOAP_BUBBLE: Self = Self {
         colour: Vec3::new(1.0, 1.0, 1.0),
-        refractive_index: 1.0/1.5,
+        refractive_index: 1.0,
         roughness: 0.0,
+        priority: 0,
+        dispersion: None,
+        thin_film: Some(ThinFilm::SOAP),
+        tint: None,
     };
-    pub(crate) const AIR_BUBBLE: Self = Self {
+    /// Wins over whatever it's inside of
+    pub const AIR_BUBBLE: Self = Self {
         colour: Vec3::new(1.0, 1.0, 1.0),
-        refractive_index: 1.00 / 1.3333,
+        refractive_index: 1.0,
+        roughness: 0.0,
+        priority: 2,
+        dispersion: None,
+        thin_film: None,
+        tint: None,
+    };
+    pub const FROSTED_GLASS: Self = Self {
+        colour: Vec3::new(1.0, 1.0, 1.0),
+        refractive_index: 1.5,
+        roughness: 0.3,
+        priority: 0,
+        dispersion: None,
+        thin_film: None,
+        tint: None,
+    };
+    pub const GREEN_BOTTLE_GLASS: Self = Self {
+        colour: Vec3::new(0.55, 0.85, 0.6),
+        refractive_index: 1.52,
         roughness: 0.0,
+        priority: 0,
+        dispersion: None,
+        thin_film: None,
+        tint: None,
+    };
+
+    // Dispersive glasses, the plain refractive index is at 589nm for RGB mode.
+    // Sellmeier coefficients from https://refractiveindex.info
+
+    pub const DIAMOND: Self = Self {
+        colour: Vec3::new(1.0, 1.0, 1.0),
+        refractive_index: 2.417,
+        roughness: 0.0,
+        priority: 0,
+        dispersion: Some(Dispersion::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030625, 0.011236, 0.0],
+        }),
+        thin_film: None,
+        tint: None,
+    };
+    /// Borosilicate crown glass, common for lenses
+    pub const BK7: Self = Self {
+        colour: Vec3::new(1.0, 1.0, 1.0),
+        refractive_index: 1.5168,
+        roughness: 0.0,
+        priority: 0,
+        dispersion: Some(Dispersion::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        }),
+        thin_film: None,
+        tint: None,
+    };
+    /// Dense flint glass (SF11), strongly dispersive so good for prisms
+    pub const DENSE_FLINT: Self = Self {
+        colour: Vec3::new(1.0, 1.0, 1.0),
+        refractive_index: 1.7847,
+        roughness: 0.0,
+        priority: 0,
+        dispersion: Some(Dispersion::Sellmeier {
+            b: [1.737_597, 0.313_747_35, 1.898_781],
+            c: [0.013_188_707, 0.062_306_814, 155.236_3],
+        }),
+        thin_film: None,
+        tint: None,
     };
 }
 
+impl Clear {
+    /// The id is the material's address. That's fixed because a `RenderObject` boxes its material (along with
+    /// any `Clear` inside it, like a `Subsurface`'s surface) and the scene doesn't move it once built, so a
+    /// `Clear` used anywhere else mustn't rely on nesting.
+    pub(crate) fn entry(&self) -> MediumEntry {
+        MediumEntry {
+            id: self as *const Self as usize,
+            refractive_index: self.refractive_index,
+            priority: self.priority,
+            transmittance: self.colour,
+            dispersion: self.dispersion,
+        }
+    }
+}
+
 impl RenderMaterial for Clear {
     fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        let entry = self.entry();
+        let media = hit.ray.media();
 
-        let ri = if hit.on_outside() {
-            1.0 / self.refractive_index
+        // The media on the far side of the surface if the ray goes through it
+        let through = if hit.on_outside() {
+            media.pushed(entry)
         } else {
-            self.refractive_index
+            media.removed(entry.id)
         };
-        let cos_theta = (-hit.ray.direction()).dot(hit.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
-        let cannot_refract = ri * sin_theta > 1.0;
+        // Inside something with a higher priority this surface doesn't exist, carry straight on
+        let other_media = if hit.on_outside() { media } else { through };
+        if other_media.overrides(self.priority) {
+            return Some(Ray::new(hit.impact, hit.direction()).with_media(through));
+        }
 
-        let direction = if cannot_refract || reflectance(cos_theta, ri) > random::<f32>() {
-            // This means we reflect
-            bounce_across_normal(hit.ray.direction(), hit.normal)
-        } else {
-            // this means we refract
-            hit.ray.direction().refract(hit.normal, ri)
+        // The stack can be missing this medium: a full stack drops the push on the way in, and a camera that
+        // starts inside never pushed it. The boundary still has glass on one side, so bend by that.
+        let (near, far) = match (hit.on_outside(), through.contains(entry.id), media.contains(entry.id)) {
+            (true, false, _) => (media.current(), entry),
+            (false, _, false) => (entry, media.current()),
+            _ => (media.current(), through.current()),
         };
-        // let direction = hit.ray.direction().refract(hit.normal, ri);
-        Some(Ray::new(hit.impact, direction))
+        let wavelengths = hit.ray.wavelengths();
+        let ri = near.refractive_index_at(wavelengths) / far.refractive_index_at(wavelengths);
+
+        let frame = ShadingFrame::new(hit.normal);
+        let wo = frame.to_local(-hit.direction());
+        if wo.z <= 1e-6 {
+            return None;
+        }
+
+        let alpha = Vec2::splat(roughness_to_alpha(self.roughness));
+        let (direction, weight) = match &self.thin_film {
+            Some(film) => {
+                let channels = ThinFilm::channel_wavelengths(&hit.ray);
+                let (n1, n3) = (near.refractive_index_at(wavelengths), far.refractive_index_at(wavelengths));
+                let reflectance = |cos| film.reflectance(cos, n1, Vec3::splat(n3), Vec3::ZERO, channels, &hit);
+                sample_rough_dielectric_with(wo, alpha, ri, reflectance)?
+            }
+            None => {
+                let (direction, weight) = sample_rough_dielectric(wo, alpha, ri)?;
+                (direction, Vec3::splat(weight))
+            }
+        };
+        let transmitted = direction.z < 0.0;
+        let weight = match &self.tint {
+            Some(tint) if transmitted => {
+                let tint = tint.sample(&hit);
+                weight * wavelengths.map_or(tint, |wavelengths| spectrum::upsample(tint, wavelengths))
+            }
+            _ => weight,
+        };
+        let differential = match (alpha.x <= SPECULAR_ALPHA, transmitted) {
+            (false, _) => None,
+            (true, true) => hit.refracted_differential(hit.normal, ri),
+            (true, false) => hit.reflected_differential(hit.normal),
+        };
+        let media = if transmitted { through } else { media };
+        let mut ray = Ray::new(hit.impact, frame.to_world(direction))
+            .with_media(media)
+            .with_attenuation_per_wavelength(weight)
+            .with_differential(differential);
+
+        // Each wavelength would have gone a different way, only the hero carries on
+        let dispersive = near.dispersion.is_some() || far.dispersion.is_some();
+        if let Some(wavelengths) = wavelengths.filter(|_| dispersive) {
+            ray = ray.with_wavelengths(wavelengths.terminate_secondary());
+        }
+        Some(ray)
     }
 
-    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+    /// Absorption inside is handled by the scene, using whichever medium the ray is travelling through
+    fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
         future_colour
     }
-}
\ No newline at end of file
+}
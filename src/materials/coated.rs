@@ -0,0 +1,125 @@
+use crate::hit::Hit;
+use crate::materials::material::{split_colour, tint_ray, RenderMaterial};
+use crate::materials::metal::Metal;
+use crate::materials::microfacet::{roughness_to_alpha, sample_rough_dielectric, ShadingFrame};
+use crate::{Ray, Vec3Colour};
+use glam::{Vec2, Vec3};
+
+/// A clear dielectric coating over any other material, like the clear coat on car paint or varnish on wood.
+///
+/// Light either reflects off the top of the coating or goes into it, then bounces between the base and the
+/// underside of the coating until it gets back out. The coating is treated as infinitely thin, so light
+/// comes back out where it went in.
+#[derive(Debug)]
+pub struct Coated {
+    base: Box<dyn RenderMaterial>,
+    refractive_index: f32,
+    roughness: f32,
+    /// Fraction of each channel left after passing straight through the coating once
+    colour: Vec3Colour,
+}
+
+impl Coated {
+    /// Bounces inside the coating before giving up on the light getting out
+    const MAX_INTERNAL_BOUNCES: usize = 8;
+
+    pub fn new(base: impl RenderMaterial + 'static, refractive_index: f32, roughness: f32) -> Self {
+        Self {
+            base: Box::new(base),
+            refractive_index,
+            roughness,
+            colour: Vec3Colour::ONE,
+        }
+    }
+
+    /// Tints the coating, light going through at an angle travels further so gets tinted more
+    pub fn with_colour(self, colour: Vec3Colour) -> Self {
+        Self { colour, ..self }
+    }
+
+    /// Glossy clear coat over a rough metallic base
+    pub fn car_paint(colour: Vec3Colour) -> Self {
+        Self::new(Metal::new(colour, 0.4), 1.5, 0.02)
+    }
+
+    /// Slightly amber varnish, for wood
+    pub fn varnish(base: impl RenderMaterial + 'static) -> Self {
+        Self::new(base, 1.5, 0.05).with_colour(Vec3::new(0.95, 0.85, 0.7))
+    }
+
+    fn alpha(&self) -> Vec2 {
+        Vec2::splat(roughness_to_alpha(self.roughness))
+    }
+
+    /// Tint for a pass through the coating in `direction`
+    fn absorption(&self, direction: Vec3, normal: Vec3) -> Vec3Colour {
+        let cos = direction.dot(normal).abs().max(1e-3);
+        self.colour.powf(1.0 / cos)
+    }
+}
+
+impl RenderMaterial for Coated {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        let outside = ShadingFrame::new(hit.normal);
+        let inside = ShadingFrame::new(-hit.normal);
+        let wo = outside.to_local(-hit.direction());
+        if wo.z <= 1e-6 {
+            return None;
+        }
+
+        // Off the top of the coating, or into it
+        let (direction, weight) = sample_rough_dielectric(wo, self.alpha(), 1.0 / self.refractive_index)?;
+        if direction.z > 0.0 {
+            return Some(Ray::new(hit.impact, outside.to_world(direction)).with_attenuation(Vec3::splat(weight)));
+        }
+
+        let mut down = outside.to_world(direction);
+        let mut tint = weight * self.absorption(down, hit.normal);
+        let mut per_wavelength: Option<Vec3> = None;
+
+        for _ in 0..Self::MAX_INTERNAL_BOUNCES {
+            // The base sees the ray as it came in, wavelengths, media and footprint and all, only bent by
+            // the coating
+            let base_hit = Hit {
+                ray: hit.ray.with_direction(down),
+                ..hit
+            };
+            let (albedo, _) = split_colour(self.base.as_ref(), base_hit);
+            let base_ray = self.base.scatter_ray(base_hit)?;
+            if base_ray.attenuation_per_wavelength() {
+                per_wavelength = Some(per_wavelength.unwrap_or(Vec3::ONE) * base_ray.attenuation());
+            } else {
+                tint *= base_ray.attenuation();
+            }
+            tint *= albedo;
+
+            // Bases that transmit light aren't supported, it's lost
+            let up = base_ray.direction();
+            if up.dot(hit.normal) <= 0.0 {
+                return None;
+            }
+            tint *= self.absorption(up, hit.normal);
+
+            // Out through the underside of the coating, or reflected back down to the base
+            let wo = inside.to_local(-up);
+            let (direction, weight) = sample_rough_dielectric(wo, self.alpha(), self.refractive_index)?;
+            tint *= weight;
+            if direction.z < 0.0 {
+                let ray = Ray::new(hit.impact, inside.to_world(direction));
+                let ray = match per_wavelength {
+                    Some(attenuation) => ray.with_attenuation_per_wavelength(attenuation),
+                    None => ray,
+                };
+                return Some(tint_ray(ray, tint, &hit.ray));
+            }
+            down = inside.to_world(direction);
+            tint *= self.absorption(down, hit.normal);
+        }
+        None
+    }
+
+    /// Whatever the base emits shines through the coating
+    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        future_colour + self.base.colour(hit, Vec3Colour::ZERO)
+    }
+}
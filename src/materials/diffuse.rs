@@ -1,18 +1,21 @@
 use crate::hit::Hit;
 use crate::materials::material::RenderMaterial;
+use crate::materials::texture::Texture;
 use crate::utils::random_point_on_unit_sphere;
 use crate::{Ray, Vec3Colour};
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Diffuse {
-    base_colour: Vec3Colour,
+    base_colour: Arc<dyn Texture>,
     roughness: f32,
 }
 
 impl Diffuse {
-    pub(crate) fn new(base_colour: Vec3Colour, roughness: f32) -> Self {
+    /// `base_colour` can be a plain colour or any texture
+    pub(crate) fn new(base_colour: impl Texture + 'static, roughness: f32) -> Self {
         Self {
-            base_colour,
+            base_colour: Arc::new(base_colour),
             roughness,
         }
     }
@@ -24,7 +27,9 @@ impl RenderMaterial for Diffuse {
         Some(Ray::new(hit.impact, dir))
     }
 
-    fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
-        self.base_colour * future_colour
+    /// Vertex colours of the geometry tint the base colour
+    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        let tint = hit.surface_colour.unwrap_or(Vec3Colour::ONE);
+        self.base_colour.sample(&hit) * tint * future_colour
     }
 }
@@ -0,0 +1,53 @@
+use crate::hit::Hit;
+use crate::materials::material::RenderMaterial;
+use crate::materials::texture::Texture;
+use crate::{Ray, Vec3Colour};
+use std::sync::Arc;
+
+/// Light given off by a surface, optionally varying across it with a texture (screens, lava, city lights)
+#[derive(Debug, Clone)]
+pub struct Emission {
+    colour: Arc<dyn Texture>,
+    strength: f32,
+}
+
+impl Emission {
+    /// `colour` can be a plain colour or a texture, like an `ImageTexture` of a screen
+    pub fn new(colour: impl Texture + 'static, strength: f32) -> Self {
+        Self {
+            colour: Arc::new(colour),
+            strength,
+        }
+    }
+
+    pub fn at(&self, hit: &Hit) -> Vec3Colour {
+        self.colour.sample(hit) * self.strength
+    }
+}
+
+/// Any material that also glows. Unlike a `LightSource` the path carries on, so it still reflects light.
+/// Made with `RenderMaterial::with_emission`.
+#[derive(Debug)]
+pub struct Emissive {
+    base: Box<dyn RenderMaterial>,
+    emission: Emission,
+}
+
+impl Emissive {
+    pub fn new(base: impl RenderMaterial + 'static, emission: Emission) -> Self {
+        Self {
+            base: Box::new(base),
+            emission,
+        }
+    }
+}
+
+impl RenderMaterial for Emissive {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        self.base.scatter_ray(hit)
+    }
+
+    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        self.base.colour(hit, future_colour) + self.emission.at(&hit)
+    }
+}
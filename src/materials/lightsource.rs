@@ -2,6 +2,8 @@ use crate::hit::Hit;
 use crate::materials::material::RenderMaterial;
 use crate::{Ray, Vec3Colour};
 
+/// A pure emitter that reflects nothing, so paths end here.
+/// For surfaces that glow and also reflect light, use `RenderMaterial::with_emission` on another material.
 #[derive(Debug)]
 pub struct LightSource {
     colour: Vec3Colour,
@@ -0,0 +1,98 @@
+use crate::hit::Hit;
+use crate::intersections::voxel_grid::VoxelGrid;
+use crate::materials::material::RenderMaterial;
+use crate::utils::{sample_free_flight, sample_henyey_greenstein};
+use crate::{Ray, Vec3Colour};
+use std::sync::Arc;
+
+/// A homogeneous participating medium such as fog, smoke or murky water.
+/// Used as the material of a `Volume`, or as the scene wide fog.
+#[derive(Debug, Clone)]
+pub struct Medium {
+    /// Probability per unit length of being absorbed
+    absorption: f32,
+    /// Probability per unit length of being scattered
+    scattering: f32,
+    colour: Vec3Colour,
+    /// Henyey-Greenstein asymmetry, 0 is isotropic, towards 1 is forward scattering and towards -1 backward
+    anisotropy: f32,
+}
+
+impl Medium {
+    pub fn new(absorption: f32, scattering: f32, colour: Vec3Colour, anisotropy: f32) -> Self {
+        Self {
+            absorption,
+            scattering,
+            colour,
+            anisotropy: anisotropy.clamp(-0.99, 0.99),
+        }
+    }
+
+    pub fn extinction(&self) -> f32 {
+        self.absorption + self.scattering
+    }
+
+    /// Fraction of interactions that scatter rather than absorb
+    pub fn albedo(&self) -> f32 {
+        if self.extinction() <= 0.0 {
+            return 0.0;
+        }
+        self.scattering / self.extinction()
+    }
+
+    /// Distance travelled before the next interaction, infinite if the medium is empty
+    pub fn sample_distance(&self) -> f32 {
+        sample_free_flight(self.extinction())
+    }
+}
+
+impl RenderMaterial for Medium {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        let direction = sample_henyey_greenstein(hit.direction(), self.anisotropy);
+        Some(Ray::new(hit.impact, direction))
+    }
+
+    fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        self.colour * self.albedo() * future_colour
+    }
+}
+
+/// The material of a `GridVolume`, a `Medium` whose coefficients are per unit density,
+/// optionally glowing with the colour stored in an emission grid.
+#[derive(Debug)]
+pub struct GridMedium {
+    medium: Medium,
+    emission: Option<Arc<VoxelGrid>>,
+    emission_strength: f32,
+}
+
+impl GridMedium {
+    pub fn new(medium: Medium, emission: Option<Arc<VoxelGrid>>, emission_strength: f32) -> Self {
+        Self {
+            medium,
+            emission,
+            emission_strength,
+        }
+    }
+
+    pub fn medium(&self) -> &Medium {
+        &self.medium
+    }
+}
+
+impl RenderMaterial for GridMedium {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        self.medium.scatter_ray(hit)
+    }
+
+    /// Collision estimator: each real collision picks up the emission weighted by the chance it was an
+    /// absorption, and carries on weighted by the chance it was a scatter
+    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        let emitted = self
+            .emission
+            .as_ref()
+            .map(|grid| grid.sample_colour(hit.impact) * self.emission_strength)
+            .unwrap_or(Vec3Colour::ZERO);
+        emitted * (1.0 - self.medium.albedo()) + self.medium.colour(hit, future_colour)
+    }
+}
@@ -1,43 +1,172 @@
 use crate::hit::Hit;
 use crate::materials::material::RenderMaterial;
+use crate::materials::microfacet::*;
+use crate::materials::texture::Texture;
+use crate::materials::thin_film::ThinFilm;
+use crate::spectrum::rgb_at_wavelength;
 use crate::{Ray, Vec3Colour};
-use crate::utils::{bounce_across_normal, random_point_on_unit_sphere};
+use glam::{Vec2, Vec3};
+use std::sync::Arc;
 
-#[derive(Debug, Copy, Clone)]
+/// GGX microfacet conductor. Reflectance comes from the metal's complex refractive index `eta + i k`,
+/// given per RGB channel (sampled at roughly 650, 550 and 450nm), so the colour shift towards white
+/// at grazing angles comes out right.
+///
+/// Roughness can differ along the surface's u direction and across it for brushed metals.
+#[derive(Debug, Clone)]
 pub(crate) struct Metal {
-    base_colour: Vec3Colour,
-    roughness: f32
+    eta: Vec3,
+    k: Vec3,
+    /// Perceptual roughness along u and v
+    roughness: Vec2,
+    /// Replaces the fixed roughness with one that varies across the surface
+    roughness_texture: Option<Arc<dyn Texture>>,
+    thin_film: Option<ThinFilm>,
 }
 
 impl Metal {
+    /// A metal with the given colour when looked at head on
     pub(crate) fn new(base_colour: Vec3Colour, roughness: f32) -> Self {
+        let (eta, k) = complex_ior_from_colour(base_colour, Vec3::ONE);
+        Self::new_from_ior(eta, k, roughness)
+    }
+
+    pub(crate) fn new_from_ior(eta: Vec3, k: Vec3, roughness: f32) -> Self {
+        Self {
+            eta,
+            k,
+            roughness: Vec2::splat(roughness),
+            roughness_texture: None,
+            thin_film: None,
+        }
+    }
+
+    /// Coats the metal in a thin film, like the oxide layer on heat tinted steel or titanium
+    pub(crate) fn with_thin_film(self, thin_film: ThinFilm) -> Self {
+        Self {
+            thin_film: Some(thin_film),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_roughness(self, roughness: f32) -> Self {
+        self.with_anisotropic_roughness(roughness, roughness)
+    }
+
+    /// Different roughness along the u direction of the surface and across it, like brushed metal
+    pub(crate) fn with_anisotropic_roughness(self, along_u: f32, along_v: f32) -> Self {
+        Self {
+            roughness: Vec2::new(along_u, along_v),
+            ..self
+        }
+    }
+
+    /// Roughness read from a texture, for scuffs and fingerprints
+    pub(crate) fn with_roughness_texture(self, roughness: impl Texture + 'static) -> Self {
         Self {
-            base_colour,
-            roughness,
+            roughness_texture: Some(Arc::new(roughness)),
+            ..self
         }
     }
+
+    // Optical constants from https://refractiveindex.info, polished by default
+
+    pub(crate) const GOLD: Self = Self {
+        eta: Vec3::new(0.143, 0.374, 1.442),
+        k: Vec3::new(3.983, 2.385, 1.603),
+        roughness: Vec2::ZERO,
+        roughness_texture: None,
+        thin_film: None,
+    };
+    pub(crate) const SILVER: Self = Self {
+        eta: Vec3::new(0.155, 0.117, 0.138),
+        k: Vec3::new(4.828, 3.122, 2.147),
+        roughness: Vec2::ZERO,
+        roughness_texture: None,
+        thin_film: None,
+    };
+    pub(crate) const COPPER: Self = Self {
+        eta: Vec3::new(0.200, 0.924, 1.102),
+        k: Vec3::new(3.912, 2.452, 2.142),
+        roughness: Vec2::ZERO,
+        roughness_texture: None,
+        thin_film: None,
+    };
+    pub(crate) const ALUMINIUM: Self = Self {
+        eta: Vec3::new(1.657, 0.880, 0.521),
+        k: Vec3::new(9.224, 6.270, 4.837),
+        roughness: Vec2::ZERO,
+        roughness_texture: None,
+        thin_film: None,
+    };
+    pub(crate) const CHROME: Self = Self {
+        eta: Vec3::new(3.105, 3.190, 2.380),
+        k: Vec3::new(3.324, 3.332, 3.253),
+        roughness: Vec2::ZERO,
+        roughness_texture: None,
+        thin_film: None,
+    };
+
+    fn alpha(&self, hit: &Hit) -> Vec2 {
+        let roughness = match &self.roughness_texture {
+            Some(texture) => Vec2::splat(texture.sample_scalar(hit)),
+            None => self.roughness,
+        };
+        Vec2::new(roughness_to_alpha(roughness.x), roughness_to_alpha(roughness.y))
+    }
+}
+
+/// Complex refractive index with the given reflectance head on and towards the edges.
+/// Gulbrandsen 2014, "Artist Friendly Metallic Fresnel".
+fn complex_ior_from_colour(reflectivity: Vec3Colour, edge_tint: Vec3Colour) -> (Vec3, Vec3) {
+    let r = reflectivity.clamp(Vec3::ZERO, Vec3::splat(0.999));
+    let g = edge_tint.clamp(Vec3::ZERO, Vec3::ONE);
+    let sqrt_r = r.powf(0.5);
+
+    let n_min = (1.0 - r) / (1.0 + r);
+    let n_max = (1.0 + sqrt_r) / (1.0 - sqrt_r);
+    let eta = g * n_min + (1.0 - g) * n_max;
+
+    let k2 = ((eta + 1.0) * (eta + 1.0) * r - (eta - 1.0) * (eta - 1.0)) / (1.0 - r);
+    (eta, k2.max(Vec3::ZERO).powf(0.5))
 }
 
 impl RenderMaterial for Metal {
+    /// Reflects off a microfacet normal drawn from the visible normals, the ray carries the Fresnel and shadowing
     fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
-        let new_dir = bounce_across_normal(hit.ray.direction(), hit.normal);
-        let rand_unit_sph = random_point_on_unit_sphere();
-        let new_dir_fuzzed = new_dir + rand_unit_sph * self.roughness;
-        Some(Ray::new(hit.impact, new_dir_fuzzed))
+        let frame = ShadingFrame::new_with_tangent(hit.normal, hit.uv_derivatives.0);
+        let wo = frame.to_local(-hit.direction());
+        if wo.z <= 1e-6 {
+            return None;
+        }
+
+        let alpha = self.alpha(&hit);
+        let m = sample_ggx_vndf(wo, alpha);
+        let wi = reflect(wo, m);
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        let shadowing = shadowing_given_masking(wo, wi, alpha);
+        let differential = if alpha.max_element() <= SPECULAR_ALPHA {
+            hit.reflected_differential(frame.to_world(m))
+        } else {
+            None
+        };
+        let ray = Ray::new(hit.impact, frame.to_world(wi)).with_differential(differential);
+        match &self.thin_film {
+            Some(film) => {
+                let channels = ThinFilm::channel_wavelengths(&hit.ray);
+                let eta = channels.map(|lambda| rgb_at_wavelength(self.eta, lambda));
+                let k = channels.map(|lambda| rgb_at_wavelength(self.k, lambda));
+                let reflectance = film.reflectance(wo.dot(m), 1.0, eta, k, channels, &hit);
+                Some(ray.with_attenuation_per_wavelength(reflectance * shadowing))
+            }
+            None => Some(ray.with_attenuation(fresnel_conductor(wo.dot(m), self.eta, self.k) * shadowing)),
+        }
     }
 
     fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
-        future_colour * self.base_colour
+        future_colour
     }
 }
-
-// impl RenderMaterial for Diffuse {
-//     fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
-//         let dir = hit.normal + random_point_on_unit_sphere() * self.roughness;
-//         Some(Ray::new(hit.impact, dir))
-//     }
-//
-//     fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
-//         self.base_colour * future_colour
-//     }
-// }
\ No newline at end of file
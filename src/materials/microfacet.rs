@@ -0,0 +1,196 @@
+//! GGX (Trowbridge-Reitz) microfacet helpers shared by the rough materials.
+//!
+//! Everything here works in a local shading frame where the surface normal is +Z and both directions
+//! point away from the surface, see `ShadingFrame`.
+
+use crate::utils::{build_orthonormal_basis, local_to_world};
+use glam::{Vec2, Vec3};
+use rand::random;
+use std::f32::consts::PI;
+
+/// Orthonormal frame around a shading normal for moving directions in and out of local space
+#[derive(Debug, Clone, Copy)]
+pub struct ShadingFrame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl ShadingFrame {
+    pub fn new(normal: Vec3) -> Self {
+        let (tangent, bitangent, normal) = build_orthonormal_basis(normal);
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    /// Lines the frame's x axis up with `tangent` (e.g. the direction of brushing), falling back to an
+    /// arbitrary frame if the tangent is missing or parallel to the normal
+    pub fn new_with_tangent(normal: Vec3, tangent: Vec3) -> Self {
+        let normal = normal.normalize();
+        match (tangent - normal * tangent.dot(normal)).try_normalize() {
+            Some(tangent) => Self {
+                tangent,
+                bitangent: normal.cross(tangent),
+                normal,
+            },
+            None => Self::new(normal),
+        }
+    }
+
+    pub fn to_local(self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+
+    pub fn to_world(self, v: Vec3) -> Vec3 {
+        local_to_world(v, self.tangent, self.bitangent, self.normal)
+    }
+}
+
+/// Below this a lobe is close enough to a mirror for ray differentials to be followed through it
+pub const SPECULAR_ALPHA: f32 = 0.01;
+
+/// Converts a perceptual roughness in [0, 1] into the GGX alpha, kept away from zero so the maths stays finite
+pub fn roughness_to_alpha(roughness: f32) -> f32 {
+    roughness.clamp(0.0, 1.0).powi(2).max(1e-3)
+}
+
+/// Distribution of microfacet normals `m`
+pub fn ggx_d(m: Vec3, alpha: Vec2) -> f32 {
+    if m.z <= 0.0 {
+        return 0.0;
+    }
+    let t = (m.x / alpha.x).powi(2) + (m.y / alpha.y).powi(2) + m.z.powi(2);
+    1.0 / (PI * alpha.x * alpha.y * t * t)
+}
+
+/// Smith's auxiliary function for the direction `w`
+fn smith_lambda(w: Vec3, alpha: Vec2) -> f32 {
+    if w.z.abs() < 1e-6 {
+        return f32::INFINITY;
+    }
+    let tan2 = ((alpha.x * w.x).powi(2) + (alpha.y * w.y).powi(2)) / (w.z * w.z);
+    ((1.0 + tan2).sqrt() - 1.0) * 0.5
+}
+
+/// Fraction of the microfacets visible from one direction
+pub fn smith_g1(w: Vec3, alpha: Vec2) -> f32 {
+    1.0 / (1.0 + smith_lambda(w, alpha))
+}
+
+/// Height correlated fraction of the microfacets visible from both directions
+pub fn smith_g2(wo: Vec3, wi: Vec3, alpha: Vec2) -> f32 {
+    1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+/// `G2 / G1`, the weight left on a ray scattered off a normal from `sample_ggx_vndf` besides the Fresnel term.
+/// `wi` is mirrored to the upper side for transmission.
+pub fn shadowing_given_masking(wo: Vec3, wi: Vec3, alpha: Vec2) -> f32 {
+    smith_g2(wo, wi, alpha) / smith_g1(wo, alpha)
+}
+
+/// Samples a microfacet normal from the distribution of normals visible from `wo`.
+/// Heitz 2018, "Sampling the GGX Distribution of Visible Normals".
+pub fn sample_ggx_vndf(wo: Vec3, alpha: Vec2) -> Vec3 {
+    let vh = Vec3::new(alpha.x * wo.x, alpha.y * wo.y, wo.z).normalize();
+
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len_sq > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt()
+    } else {
+        Vec3::X
+    };
+    let t2 = vh.cross(t1);
+
+    let r = random::<f32>().sqrt();
+    let phi = 2.0 * PI * random::<f32>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+    Vec3::new(alpha.x * nh.x, alpha.y * nh.y, nh.z.max(0.0)).normalize()
+}
+
+/// Reflects `wo` about the microfacet normal `m`, both pointing away from the surface
+pub fn reflect(wo: Vec3, m: Vec3) -> Vec3 {
+    2.0 * wo.dot(m) * m - wo
+}
+
+/// Rough dielectric: reflects or refracts through a visible microfacet with the exact Fresnel probability,
+/// so only the shadowing is left in the weight. Returns (direction, weight), the direction is below the
+/// surface when the ray was transmitted. `eta` is the incoming side's refractive index over the far side's.
+pub fn sample_rough_dielectric(wo: Vec3, alpha: Vec2, eta: f32) -> Option<(Vec3, f32)> {
+    sample_rough_dielectric_with(wo, alpha, eta, |cos| Vec3::splat(fresnel_dielectric(cos, eta)))
+        .map(|(wi, weight)| (wi, weight.x))
+}
+
+/// `sample_rough_dielectric` with a reflectance that can differ per channel, like from a thin film.
+/// Reflects with the average reflectance's probability, and weights each channel to make up the difference.
+pub fn sample_rough_dielectric_with(
+    wo: Vec3,
+    alpha: Vec2,
+    eta: f32,
+    reflectance: impl Fn(f32) -> Vec3,
+) -> Option<(Vec3, Vec3)> {
+    let m = sample_ggx_vndf(wo, alpha);
+    let reflectance = reflectance(wo.dot(m)).clamp(Vec3::ZERO, Vec3::ONE);
+    let probability = reflectance.element_sum() / 3.0;
+
+    if random::<f32>() < probability {
+        let wi = reflect(wo, m);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some((wi, reflectance / probability * shadowing_given_masking(wo, wi, alpha)))
+    } else {
+        let wi = (-wo).refract(m, eta);
+        if wi.z >= 0.0 || wi == Vec3::ZERO {
+            return None;
+        }
+        let transmittance = (Vec3::ONE - reflectance) / (1.0 - probability);
+        Some((wi, transmittance * shadowing_given_masking(wo, -wi, alpha)))
+    }
+}
+
+/// Unpolarised Fresnel reflectance of a dielectric boundary, `eta` is the ratio of the refractive index
+/// on the incoming side over the one on the far side
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// Unpolarised Fresnel reflectance of a conductor with complex refractive index `eta + i k` per channel
+/// (relative to the outside medium). https://seblagarde.wordpress.com/2013/04/29/memo-on-fresnel-equations/
+pub fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).powf(0.5);
+    let a = (0.5 * (a2_plus_b2 + t0)).max(Vec3::ZERO).powf(0.5);
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * a * cos2.sqrt();
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rs + rp)
+}
+
+/// Schlick's approximation with a coloured reflectance at normal incidence
+pub fn fresnel_schlick(cos_theta: f32, f0: Vec3) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
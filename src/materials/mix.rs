@@ -0,0 +1,57 @@
+use crate::hit::Hit;
+use crate::materials::material::{split_colour, tint_ray, RenderMaterial};
+use crate::materials::texture::Texture;
+use crate::{Ray, Vec3Colour};
+use rand::random;
+use std::sync::Arc;
+
+/// Blend of two materials, e.g. rust patches on metal or dirt on paint.
+/// Each scatter picks one of the two by the weight, and emission from both is blended.
+#[derive(Debug)]
+pub struct Mix {
+    a: Box<dyn RenderMaterial>,
+    b: Box<dyn RenderMaterial>,
+    /// How much of `b` there is at a point
+    weight: Arc<dyn Texture>,
+}
+
+impl Mix {
+    /// `weight` is how much of `b` there is, 0 is all `a`. A texture works as a mask, white being `b`.
+    pub fn new(
+        a: impl RenderMaterial + 'static,
+        b: impl RenderMaterial + 'static,
+        weight: impl Texture + 'static,
+    ) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+            weight: Arc::new(weight),
+        }
+    }
+
+    fn weight_at(&self, hit: &Hit) -> f32 {
+        self.weight.sample_scalar(hit).clamp(0.0, 1.0)
+    }
+}
+
+impl RenderMaterial for Mix {
+    /// Picking a material with probability equal to its weight cancels the weight out,
+    /// leaving just the picked material's albedo on the ray
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        let material = if random::<f32>() < self.weight_at(&hit) {
+            &self.b
+        } else {
+            &self.a
+        };
+        let (albedo, _) = split_colour(material.as_ref(), hit);
+        let ray = material.scatter_ray(hit)?;
+        Some(tint_ray(ray, albedo, &hit.ray))
+    }
+
+    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        let weight = self.weight_at(&hit);
+        let emitted_a = self.a.colour(hit, Vec3Colour::ZERO);
+        let emitted_b = self.b.colour(hit, Vec3Colour::ZERO);
+        future_colour + emitted_a.lerp(emitted_b, weight)
+    }
+}
@@ -4,3 +4,16 @@ pub mod metal;
 pub mod clear;
 pub mod lightsource;
 pub mod texture;
+pub mod medium;
+pub mod subsurface;
+pub mod microfacet;
+pub mod principled;
+pub mod thin_film;
+pub mod coated;
+pub mod mix;
+pub mod emission;
+pub mod normal_map;
+pub mod textured_mirror;
+pub mod procedural;
+pub mod texture_set;
+pub mod projection;
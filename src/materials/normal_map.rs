@@ -0,0 +1,153 @@
+use crate::hit::Hit;
+use crate::materials::material::RenderMaterial;
+use crate::materials::texture::Texture;
+use crate::{Ray, Vec3Colour};
+use glam::{Vec2, Vec3};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Something that bends the shading normal to fake detail the geometry doesn't have
+pub trait SurfaceDetail: Debug + Sync + Send {
+    fn normal_at(&self, hit: &Hit) -> Vec3;
+
+    /// The hit as the material should see it, with the bent normal. Normals bent so far they'd face
+    /// away from the ray are left alone, or light would leak through the surface.
+    fn apply(&self, hit: Hit) -> Hit {
+        let normal = self.normal_at(&hit);
+        if normal.dot(hit.direction()) >= 0.0 {
+            return hit;
+        }
+        Hit { normal, ..hit }
+    }
+}
+
+/// The tangent (along u) and bitangent (along v) at a hit, made perpendicular to its normal
+fn tangent_frame(hit: &Hit) -> (Vec3, Vec3) {
+    let (dp_du, dp_dv) = hit.uv_derivatives;
+    let tangent = (dp_du - hit.normal * hit.normal.dot(dp_du)).normalize_or_zero();
+    let bitangent = hit.normal.cross(tangent);
+    // Keep the bitangent pointing along v, whichever way round the uv is on the surface
+    let bitangent = if bitangent.dot(dp_dv) < 0.0 { -bitangent } else { bitangent };
+    (tangent, bitangent)
+}
+
+/// Which way up a normal map's green channel is. Images have v = 0 on their top row, so v runs down them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalConvention {
+    /// Green points up the image, towards -v. Used by Blender and glTF.
+    OpenGl,
+    /// Green points down the image, along +v. Used by Unreal and 3ds Max.
+    DirectX,
+}
+
+/// Tangent space normal map, bending the shading normal by a texture whose channels hold the
+/// normal's tangent, bitangent and normal components mapped from [-1, 1] to [0, 1]
+#[derive(Debug, Clone)]
+pub struct NormalMap {
+    texture: Arc<dyn Texture>,
+    /// Scales how far the normal tilts, 0 is flat and 1 is as the map was made
+    strength: f32,
+    convention: NormalConvention,
+}
+
+impl NormalMap {
+    pub fn new(texture: impl Texture + 'static) -> Self {
+        Self {
+            texture: Arc::new(texture),
+            strength: 1.0,
+            convention: NormalConvention::OpenGl,
+        }
+    }
+
+    pub fn with_strength(self, strength: f32) -> Self {
+        Self { strength, ..self }
+    }
+
+    pub fn with_convention(self, convention: NormalConvention) -> Self {
+        Self { convention, ..self }
+    }
+}
+
+impl SurfaceDetail for NormalMap {
+    fn normal_at(&self, hit: &Hit) -> Vec3 {
+        let mut sample = self.texture.sample(hit) * 2.0 - 1.0;
+        if self.convention == NormalConvention::OpenGl {
+            sample.y = -sample.y;
+        }
+        let local = Vec3::new(sample.x * self.strength, sample.y * self.strength, sample.z.max(0.0));
+
+        let (tangent, bitangent) = tangent_frame(hit);
+        (tangent * local.x + bitangent * local.y + hit.normal * local.z)
+            .try_normalize()
+            .unwrap_or(hit.normal)
+    }
+}
+
+/// Greyscale height map, the normal leans away from where the height rises
+#[derive(Debug, Clone)]
+pub struct BumpMap {
+    texture: Arc<dyn Texture>,
+    /// Height of white above black, in world units
+    strength: f32,
+}
+
+impl BumpMap {
+    /// Step in uv for the height's finite differences
+    const DELTA: f32 = 1.0 / 2048.0;
+
+    pub fn new(texture: impl Texture + 'static, strength: f32) -> Self {
+        Self {
+            texture: Arc::new(texture),
+            strength,
+        }
+    }
+
+    fn height(&self, hit: &Hit, offset: Vec2) -> f32 {
+        let hit = Hit {
+            uv: hit.uv + offset,
+            ..*hit
+        };
+        self.texture.sample_scalar(&hit) * self.strength
+    }
+}
+
+impl SurfaceDetail for BumpMap {
+    /// The surface moved along its normal by the height has tangents `dp/du + dh/du n` and `dp/dv + dh/dv n`,
+    /// with the change in normal across the surface left out
+    fn normal_at(&self, hit: &Hit) -> Vec3 {
+        let height = self.height(hit, Vec2::ZERO);
+        let dh_du = (self.height(hit, Vec2::new(Self::DELTA, 0.0)) - height) / Self::DELTA;
+        let dh_dv = (self.height(hit, Vec2::new(0.0, Self::DELTA)) - height) / Self::DELTA;
+
+        let (dp_du, dp_dv) = hit.uv_derivatives;
+        let bumped = (dp_du + hit.normal * dh_du).cross(dp_dv + hit.normal * dh_dv);
+        let bumped = if bumped.dot(hit.normal) < 0.0 { -bumped } else { bumped };
+        bumped.try_normalize().unwrap_or(hit.normal)
+    }
+}
+
+/// Any material with its normal bent by a normal or bump map. Made with `RenderMaterial::with_normal_map`.
+#[derive(Debug)]
+pub struct NormalMapped {
+    base: Box<dyn RenderMaterial>,
+    detail: Box<dyn SurfaceDetail>,
+}
+
+impl NormalMapped {
+    pub fn new(base: impl RenderMaterial + 'static, detail: impl SurfaceDetail + 'static) -> Self {
+        Self {
+            base: Box::new(base),
+            detail: Box::new(detail),
+        }
+    }
+}
+
+impl RenderMaterial for NormalMapped {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        self.base.scatter_ray(self.detail.apply(hit))
+    }
+
+    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        self.base.colour(self.detail.apply(hit), future_colour)
+    }
+}
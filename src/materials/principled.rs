@@ -0,0 +1,380 @@
+use crate::hit::Hit;
+use crate::materials::material::RenderMaterial;
+use crate::materials::microfacet::*;
+use crate::utils::random_cosine_direction;
+use crate::{Ray, Vec3Colour};
+use glam::{Vec2, Vec3};
+use rand::random;
+
+/// The Disney "principled" BSDF (Burley 2012, 2015): one physically based material covering most real
+/// surfaces through a handful of intuitive parameters, so imported glTF/MTL materials have something to map onto.
+///
+/// Made of a diffuse lobe with retro-reflection and sheen, a GGX specular lobe, a GGX clearcoat on top,
+/// and a rough glass lobe for transmission. The lobes are layered: the clearcoat takes its Fresnel share first,
+/// and the diffuse only gets what the specular doesn't reflect, so the surface never gives out more than comes in.
+/// Each scatter picks one lobe and the ray carries its weight.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    /// Diffuse colour for dielectrics, specular colour for metals, tint of transmitted light
+    pub base_colour: Vec3Colour,
+    pub emission_colour: Vec3Colour,
+    pub index_of_refraction: f32,
+    /// 0 = opaque, 1 = fully transmissive
+    pub transmission: f32,
+    /// 0 = perfect mirror, 1 = very rough
+    pub roughness: f32,
+    /// 0 = dielectric, 1 = fully metallic
+    pub metallic: f32,
+    /// How much the dielectric specular takes on the base colour
+    pub specular_tint: f32,
+    /// Extra grazing angle reflection for cloth
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    /// Strength of a second, white specular layer
+    pub clearcoat: f32,
+    /// 0 = satin clearcoat, 1 = glossy
+    pub clearcoat_gloss: f32,
+    /// Ambient occlusion, only darkens the diffuse lobe since crevices still show reflections
+    pub occlusion: Vec3Colour,
+}
+
+impl Principled {
+    const DEFAULT: Self = Self {
+        base_colour: Vec3::ONE,
+        emission_colour: Vec3::ZERO,
+        index_of_refraction: 1.5,
+        transmission: 0.0,
+        roughness: 0.5,
+        metallic: 0.0,
+        specular_tint: 0.0,
+        sheen: 0.0,
+        sheen_tint: 0.5,
+        clearcoat: 0.0,
+        clearcoat_gloss: 1.0,
+        occlusion: Vec3::ONE,
+    };
+
+    pub fn new(
+        base_colour: Vec3Colour,
+        emission_colour: Vec3Colour,
+        index_of_refraction: f32,
+        transmission: f32,
+        roughness: f32,
+        metallic: f32,
+    ) -> Self {
+        Self {
+            base_colour,
+            emission_colour,
+            index_of_refraction,
+            transmission: transmission.clamp(0.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+            metallic: metallic.clamp(0.0, 1.0),
+            ..Self::DEFAULT
+        }
+    }
+
+    pub fn light_source(colour: Vec3Colour) -> Self {
+        Self {
+            base_colour: Vec3::ZERO,
+            emission_colour: colour,
+            ..Self::DEFAULT
+        }
+    }
+
+    pub fn with_specular_tint(self, specular_tint: f32) -> Self {
+        Self {
+            specular_tint,
+            ..self
+        }
+    }
+
+    pub fn with_sheen(self, sheen: f32, sheen_tint: f32) -> Self {
+        Self {
+            sheen,
+            sheen_tint,
+            ..self
+        }
+    }
+
+    pub fn with_clearcoat(self, clearcoat: f32, clearcoat_gloss: f32) -> Self {
+        Self {
+            clearcoat,
+            clearcoat_gloss,
+            ..self
+        }
+    }
+
+    pub fn with_occlusion(self, occlusion: Vec3Colour) -> Self {
+        Self { occlusion, ..self }
+    }
+
+    /// Perfect vacuum or air approximation
+    pub const AIR: Self = Self {
+        index_of_refraction: 1.0,
+        transmission: 1.0,
+        roughness: 0.0,
+        ..Self::DEFAULT
+    };
+
+    /// Water approximation, with a faint blue tint per pass through the surface
+    pub const WATER: Self = Self {
+        base_colour: Vec3::new(0.85, 0.95, 1.0),
+        index_of_refraction: 1.333,
+        transmission: 1.0,
+        roughness: 0.0,
+        ..Self::DEFAULT
+    };
+
+    /// Glass approximation
+    pub const GLASS: Self = Self {
+        index_of_refraction: 1.5,
+        transmission: 1.0,
+        roughness: 0.0,
+        ..Self::DEFAULT
+    };
+
+    /// Idealised mirror (perfectly reflective metal)
+    pub const MIRROR: Self = Self {
+        roughness: 0.0,
+        metallic: 1.0,
+        ..Self::DEFAULT
+    };
+
+    /// Approximate gold colour
+    pub const GOLD: Self = Self {
+        base_colour: Vec3::new(1.0, 0.78, 0.34),
+        roughness: 0.2,
+        metallic: 1.0,
+        ..Self::DEFAULT
+    };
+
+    /// A simple emissive material (blue glow)
+    pub const EMISSIVE_BLUE: Self = Self {
+        base_colour: Vec3::ZERO,
+        emission_colour: Vec3::new(0.0, 0.0, 1.0),
+        ..Self::DEFAULT
+    };
+
+    /// A generic plastic with moderate roughness
+    pub fn plastic(base_colour: Vec3Colour) -> Self {
+        Self {
+            base_colour,
+            roughness: 0.4,
+            ..Self::DEFAULT
+        }
+    }
+
+    pub fn super_matte(base_colour: Vec3Colour) -> Self {
+        Self {
+            base_colour,
+            roughness: 1.0,
+            specular_tint: 0.0,
+            index_of_refraction: 1.0,
+            ..Self::DEFAULT
+        }
+    }
+
+    /// How much of the light coming in along `wo` each of the diffuse, specular, glass and clearcoat lobes
+    /// gets, as colour scales on what each lobe's sampling returns. Sums to at most 1 per channel.
+    fn lobe_weights(&self, wo: Vec3) -> [Vec3Colour; 4] {
+        let clearcoat = self.clearcoat.clamp(0.0, 1.0) * fresnel_schlick(wo.z, Vec3::splat(0.04)).x;
+        let below = 1.0 - clearcoat;
+
+        let dielectric = 1.0 - self.metallic;
+        let opaque = dielectric * (1.0 - self.transmission);
+        let dielectric_fresnel = fresnel_schlick(wo.z, self.dielectric_f0());
+        [
+            below * opaque * (Vec3::ONE - dielectric_fresnel) * self.occlusion,
+            Vec3::splat(below * (self.metallic + opaque)),
+            Vec3::splat(below * dielectric * self.transmission),
+            Vec3::splat(self.clearcoat.clamp(0.0, 1.0)),
+        ]
+    }
+
+    /// Roughly how much light each lobe sends back, to pick between them in proportion
+    fn lobe_energies(&self, wo: Vec3, weights: &[Vec3Colour; 4]) -> [f32; 4] {
+        let mean = |x: Vec3| x.element_sum() / 3.0;
+        [
+            mean(weights[0]),
+            weights[1].x * mean(fresnel_schlick(wo.z, self.specular_f0())),
+            weights[2].x,
+            weights[3].x * fresnel_schlick(wo.z, Vec3::splat(0.04)).x,
+        ]
+    }
+
+    fn dielectric_f0(&self) -> Vec3Colour {
+        let f0 = ((self.index_of_refraction - 1.0) / (self.index_of_refraction + 1.0)).powi(2);
+        f0 * Vec3::ONE.lerp(self.tint(), self.specular_tint)
+    }
+
+    /// The specular lobe covers the metal and the opaque dielectric. Schlick is linear in f0, so mixing the
+    /// f0s in proportion gives each its own share of the reflection.
+    fn specular_f0(&self) -> Vec3Colour {
+        let opaque = (1.0 - self.metallic) * (1.0 - self.transmission);
+        let metal_share = if self.metallic + opaque > 0.0 {
+            self.metallic / (self.metallic + opaque)
+        } else {
+            0.0
+        };
+        self.dielectric_f0().lerp(self.base_colour, metal_share)
+    }
+
+    /// Base colour with its brightness taken out, used for the tints
+    fn tint(&self) -> Vec3Colour {
+        let luminance = self.base_colour.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        if luminance > 0.0 {
+            self.base_colour / luminance
+        } else {
+            Vec3::ONE
+        }
+    }
+
+    fn alpha(&self) -> Vec2 {
+        Vec2::splat(roughness_to_alpha(self.roughness))
+    }
+
+    /// Burley diffuse with retro-reflection at grazing angles, plus sheen. Cosine sampled, returns (direction, weight).
+    fn sample_diffuse(&self, wo: Vec3) -> (Vec3, Vec3Colour) {
+        let wi = random_cosine_direction(Vec3::Z).normalize();
+        let half = (wi + wo).normalize_or_zero();
+        let cos_d = wi.dot(half).max(0.0);
+
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let schlick = |cos: f32| 1.0 + (fd90 - 1.0) * (1.0 - cos.max(0.0)).powi(5);
+        let diffuse = self.base_colour * schlick(wi.z) * schlick(wo.z);
+
+        let sheen_colour = Vec3::ONE.lerp(self.tint(), self.sheen_tint);
+        let sheen = sheen_colour * self.sheen * (1.0 - cos_d).powi(5) * std::f32::consts::PI;
+
+        (wi, diffuse + sheen)
+    }
+
+    /// GGX reflection off a microfacet normal drawn from the visible normals, returns (direction, weight)
+    fn sample_reflection(wo: Vec3, alpha: Vec2, f0: Vec3Colour) -> Option<(Vec3, Vec3Colour)> {
+        let m = sample_ggx_vndf(wo, alpha);
+        let wi = reflect(wo, m);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        let fresnel = fresnel_schlick(wo.dot(m), f0);
+        Some((wi, fresnel * shadowing_given_masking(wo, wi, alpha)))
+    }
+
+    fn sample_specular(&self, wo: Vec3) -> Option<(Vec3, Vec3Colour)> {
+        Self::sample_reflection(wo, self.alpha(), self.specular_f0())
+    }
+
+    fn sample_clearcoat(&self, wo: Vec3) -> Option<(Vec3, Vec3Colour)> {
+        let alpha = Vec2::splat((0.1 + (0.001 - 0.1) * self.clearcoat_gloss).max(1e-3));
+        Self::sample_reflection(wo, alpha, Vec3::splat(0.04))
+    }
+
+    fn sample_glass(&self, wo: Vec3, outside: bool) -> Option<(Vec3, Vec3Colour)> {
+        let eta = if outside {
+            1.0 / self.index_of_refraction
+        } else {
+            self.index_of_refraction
+        };
+        let (wi, weight) = sample_rough_dielectric(wo, self.alpha(), eta)?;
+        let tint = if wi.z < 0.0 { self.base_colour } else { Vec3::ONE };
+        Some((wi, tint * weight))
+    }
+}
+
+impl RenderMaterial for Principled {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        let frame = ShadingFrame::new(hit.normal);
+        let wo = frame.to_local(-hit.direction());
+        // Too close to grazing for the shadowing terms to be meaningful
+        if wo.z <= 1e-6 {
+            return None;
+        }
+
+        // From inside a transmissive object the only way out is through the glass lobe
+        let (weights, energies) = if !hit.on_outside() && self.transmission > 0.0 {
+            ([Vec3::ZERO, Vec3::ZERO, Vec3::ONE, Vec3::ZERO], [0.0, 0.0, 1.0, 0.0])
+        } else {
+            let weights = self.lobe_weights(wo);
+            (weights, self.lobe_energies(wo, &weights))
+        };
+        let total = energies.iter().sum::<f32>();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut pick = random::<f32>() * total;
+        let lobe = energies
+            .iter()
+            .position(|e| {
+                pick -= e;
+                pick < 0.0
+            })
+            .unwrap_or(energies.iter().rposition(|e| *e > 0.0)?);
+
+        let (wi, sampled) = match lobe {
+            0 => self.sample_diffuse(wo),
+            1 => self.sample_specular(wo)?,
+            2 => self.sample_glass(wo, hit.on_outside())?,
+            _ => self.sample_clearcoat(wo)?,
+        };
+        // Divided by the chance of picking this lobe
+        let weight = weights[lobe] * sampled * total / energies[lobe];
+        Some(Ray::new(hit.impact, frame.to_world(wi)).with_attenuation(weight))
+    }
+
+    fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        self.emission_colour + future_colour
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// White furnace: a white surface lit evenly from everywhere can at most send all of it back, so the
+    /// average weight of its scattered rays must not go over 1
+    fn furnace(material: &Principled, cos_theta: f32) -> Vec3Colour {
+        const SAMPLES: usize = 200_000;
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let mut hit = Hit::new_on_surface(Vec3::ZERO, Vec3::Z, Vec2::ZERO);
+        hit.ray = Ray::new(Vec3::new(sin_theta, 0.0, cos_theta), Vec3::new(-sin_theta, 0.0, -cos_theta));
+        let total: Vec3Colour = (0..SAMPLES)
+            .filter_map(|_| material.scatter_ray(hit))
+            .map(|ray| ray.attenuation())
+            .sum();
+        total / SAMPLES as f32
+    }
+
+    #[test]
+    fn layered_lobes_do_not_gain_energy() {
+        let materials = [
+            Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.0, 0.5, 0.0),
+            Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.0, 0.1, 0.5),
+            Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.0, 0.3, 1.0),
+            Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.5, 0.2, 0.0),
+            Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.0, 0.5, 0.0).with_clearcoat(1.0, 1.0),
+            Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.0, 0.2, 1.0).with_clearcoat(1.0, 0.5),
+        ];
+        for material in &materials {
+            for cos_theta in [1.0, 0.7, 0.3] {
+                let albedo = furnace(material, cos_theta);
+                assert!(
+                    albedo.max_element() <= 1.02,
+                    "{material:?} sends back {albedo} at cos {cos_theta}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn occlusion_only_darkens_the_diffuse() {
+        let metal = Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.0, 0.3, 1.0);
+        let occluded_metal = furnace(&metal.clone().with_occlusion(Vec3::ZERO), 0.7);
+        assert!((occluded_metal - furnace(&metal, 0.7)).abs().max_element() < 0.02, "{occluded_metal}");
+
+        // Only the specular is left, a few percent at this angle
+        let plastic = Principled::new(Vec3::ONE, Vec3::ZERO, 1.5, 0.0, 0.3, 0.0);
+        let occluded_plastic = furnace(&plastic.with_occlusion(Vec3::ZERO), 0.7);
+        assert!(occluded_plastic.max_element() > 0.0 && occluded_plastic.max_element() < 0.1, "{occluded_plastic}");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::hit::Hit;
+use crate::materials::texture::Texture;
+use crate::Vec3Colour;
+use glam::{Affine3A, IVec3, Vec3};
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::f32::consts::TAU;
+
+/// Where a procedural texture is evaluated
+#[derive(Debug, Clone, Copy)]
+pub enum Space {
+    /// The surface's texture coordinates, as (u, v, 0)
+    Uv,
+    /// The impact point moved by the transform first, so the pattern can be placed, turned and stretched.
+    /// Objects are built straight in world space, so this is also how to fix a pattern to one.
+    World(Affine3A),
+}
+
+/// Improved Perlin gradient noise (Perlin 2002), shuffled by a seed so renders are repeatable
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut shuffled: Vec<u8> = (0..=255).collect();
+        shuffled.shuffle(&mut StdRng::seed_from_u64(seed));
+        let mut permutation = [0; 512];
+        permutation.iter_mut().enumerate().for_each(|(i, x)| *x = shuffled[i % 256]);
+        Self { permutation }
+    }
+
+    fn hash(&self, cell: IVec3) -> usize {
+        let p = |i: i32| self.permutation[(i & 255) as usize] as i32;
+        p(p(p(cell.x) + cell.y) + cell.z) as usize
+    }
+
+    /// Roughly in [-1, 1], zero at every whole number point
+    pub fn noise(&self, p: Vec3) -> f32 {
+        let cell = p.floor();
+        let f = p - cell;
+        let cell = cell.as_ivec3();
+        let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+        let corner = |offset: IVec3| gradient(self.hash(cell + offset), f - offset.as_vec3());
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x = |y: i32, z: i32| lerp(corner(IVec3::new(0, y, z)), corner(IVec3::new(1, y, z)), fade.x);
+        let y = |z: i32| lerp(x(0, z), x(1, z), fade.y);
+        lerp(y(0), y(1), fade.z)
+    }
+
+    /// Fractal Brownian motion, octaves of noise each at double the frequency and half the amplitude. In [-1, 1].
+    pub fn fbm(&self, p: Vec3, octaves: u32) -> f32 {
+        self.octaves(p, octaves, |x| x)
+    }
+
+    /// Like `fbm` but summing the noise's magnitude, which gives creases where it crosses zero. In [0, 1].
+    pub fn turbulence(&self, p: Vec3, octaves: u32) -> f32 {
+        self.octaves(p, octaves, f32::abs)
+    }
+
+    fn octaves(&self, p: Vec3, octaves: u32, shape: impl Fn(f32) -> f32) -> f32 {
+        let (mut sum, mut total, mut amplitude, mut p) = (0.0, 0.0, 1.0, p);
+        for _ in 0..octaves.max(1) {
+            sum += amplitude * shape(self.noise(p));
+            total += amplitude;
+            amplitude *= 0.5;
+            p *= 2.0;
+        }
+        sum / total
+    }
+
+    /// A point in the cell, each cell getting its own
+    fn feature_point(&self, cell: IVec3) -> Vec3 {
+        let h = self.hash(cell);
+        let random = |i: usize| self.permutation[(h + i * 71) & 511] as f32 / 255.0;
+        cell.as_vec3() + Vec3::new(random(0), random(1), random(2))
+    }
+
+    /// Worley noise: the distance to the nearest of a random point per cell, and a random value in [0, 1]
+    /// picked by that nearest cell
+    pub fn worley(&self, p: Vec3) -> (f32, f32) {
+        let cell = p.floor().as_ivec3();
+        let (distance, nearest) = (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+            .map(|offset| cell + offset)
+            .map(|cell| (self.feature_point(cell).distance(p), cell))
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .unwrap();
+        (distance, self.permutation[self.hash(nearest + 17)] as f32 / 255.0)
+    }
+}
+
+/// Dot product of the offset with one of 12 edge directions of a cube, picked by the hash
+fn gradient(hash: usize, offset: Vec3) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { offset.x } else { offset.y };
+    let v = match h {
+        0..=3 => offset.y,
+        12 | 14 => offset.x,
+        _ => offset.z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Pattern {
+    /// Alternating cubes, squares in uv space
+    Checker,
+    Noise,
+    Fbm { octaves: u32 },
+    Turbulence { octaves: u32 },
+    /// Veins along x, once per unit, bent by turbulence
+    Marble { octaves: u32, veins: f32 },
+    /// Rings around the z axis, wobbled by noise
+    Wood { rings: f32 },
+    /// Distance to the nearest cell centre, dark in the middle of each cell
+    Voronoi,
+    /// One flat value per Voronoi cell, like crazy paving
+    Cells,
+}
+
+/// A texture worked out from a formula instead of an image, so needs no files and has no resolution.
+/// Each pattern gives a value between 0 and 1 which picks between two colours.
+#[derive(Debug, Clone)]
+pub struct Procedural {
+    pattern: Pattern,
+    space: Space,
+    /// How many times the pattern repeats per unit of the space
+    frequency: f32,
+    low: Vec3Colour,
+    high: Vec3Colour,
+    noise: Perlin,
+}
+
+impl Procedural {
+    pub fn new(pattern: Pattern) -> Self {
+        Self {
+            pattern,
+            space: Space::Uv,
+            frequency: 1.0,
+            low: Vec3::ZERO,
+            high: Vec3::ONE,
+            noise: Perlin::new(0),
+        }
+    }
+
+    pub fn checker(low: Vec3Colour, high: Vec3Colour, frequency: f32) -> Self {
+        Self::new(Pattern::Checker).with_colours(low, high).with_frequency(frequency)
+    }
+
+    pub fn fbm(octaves: u32) -> Self {
+        Self::new(Pattern::Fbm { octaves })
+    }
+
+    pub fn turbulence(octaves: u32) -> Self {
+        Self::new(Pattern::Turbulence { octaves })
+    }
+
+    pub fn marble() -> Self {
+        Self::new(Pattern::Marble { octaves: 6, veins: 5.0 })
+            .with_colours(Vec3::new(0.3, 0.3, 0.35), Vec3::new(0.95, 0.93, 0.9))
+    }
+
+    pub fn wood() -> Self {
+        Self::new(Pattern::Wood { rings: 12.0 }).with_colours(Vec3::new(0.35, 0.18, 0.07), Vec3::new(0.7, 0.45, 0.22))
+    }
+
+    pub fn voronoi() -> Self {
+        Self::new(Pattern::Voronoi)
+    }
+
+    pub fn with_space(self, space: Space) -> Self {
+        Self { space, ..self }
+    }
+
+    pub fn with_frequency(self, frequency: f32) -> Self {
+        Self { frequency, ..self }
+    }
+
+    /// The colours at 0 and 1
+    pub fn with_colours(self, low: Vec3Colour, high: Vec3Colour) -> Self {
+        Self { low, high, ..self }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            ..self
+        }
+    }
+
+    fn point(&self, hit: &Hit) -> Vec3 {
+        let p = match self.space {
+            Space::Uv => hit.uv.extend(0.0),
+            Space::World(transform) => transform.transform_point3(hit.impact),
+        };
+        p * self.frequency
+    }
+
+    pub fn value_at(&self, p: Vec3) -> f32 {
+        let noise = &self.noise;
+        let value = match self.pattern {
+            Pattern::Checker => (p.floor().as_ivec3().element_sum() & 1) as f32,
+            Pattern::Noise => 0.5 + 0.5 * noise.noise(p),
+            Pattern::Fbm { octaves } => 0.5 + 0.5 * noise.fbm(p, octaves),
+            Pattern::Turbulence { octaves } => noise.turbulence(p, octaves),
+            Pattern::Marble { octaves, veins } => 0.5 + 0.5 * (TAU * p.x + veins * noise.turbulence(p, octaves)).sin(),
+            Pattern::Wood { rings } => {
+                let distance = p.truncate().length() + 0.1 * noise.noise(p * 4.0);
+                (distance * rings).fract()
+            }
+            Pattern::Voronoi => noise.worley(p).0,
+            Pattern::Cells => noise.worley(p).1,
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for Procedural {
+    fn sample(&self, hit: &Hit) -> Vec3Colour {
+        self.low.lerp(self.high, self.value_at(self.point(hit)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Away from whole numbers, where gradient noise is always zero
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..20).map(|i| Vec3::new(0.37 * i as f32, 1.3 - 0.21 * i as f32, 0.5 + 0.77 * i as f32))
+    }
+
+    fn values(noise: &Perlin) -> Vec<[f32; 5]> {
+        points()
+            .map(|p| {
+                let (distance, cell) = noise.worley(p);
+                [noise.noise(p), noise.fbm(p, 4), noise.turbulence(p, 4), distance, cell]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_noise() {
+        assert_eq!(values(&Perlin::new(42)), values(&Perlin::new(42)));
+
+        let marble = || Procedural::marble().with_seed(42);
+        let (a, b) = (marble(), marble());
+        assert!(points().all(|p| a.value_at(p) == b.value_at(p)));
+    }
+
+    #[test]
+    fn different_seeds_give_different_noise() {
+        let (a, b) = (values(&Perlin::new(1)), values(&Perlin::new(2)));
+        for channel in 0..5 {
+            assert!(a.iter().zip(&b).any(|(a, b)| a[channel] != b[channel]), "channel {channel} didn't change");
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::hit::{Hit, SurfaceDifferentials};
+use crate::materials::texture::Texture;
+use crate::Vec3Colour;
+use glam::{Affine3A, Mat3A, Vec2, Vec3};
+use std::f32::consts::{PI, TAU};
+use std::sync::Arc;
+
+/// How a point in projection space becomes texture coordinates. The axes are the projection space's,
+/// with z up like the rest of the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Straight down the z axis, u along x and v along y
+    Planar,
+    /// Wrapped around the z axis, u goes once round and v is the height
+    Cylindrical,
+    /// Wrapped around the origin, u is the longitude and v goes from the top pole to the bottom one
+    Spherical,
+    /// Planar along each of x, y and z, blended by how much the surface faces each way. Higher
+    /// sharpness narrows the blend, so each side shows one projection with less smearing between them.
+    Triplanar { sharpness: f32 },
+}
+
+/// The uv of a point and how the point moves with it
+type Mapping = (Vec2, (Vec3, Vec3));
+
+impl Projection {
+    /// The texture coordinates along the two axes that aren't `axis` in a triplanar projection
+    fn planar_along(axis: usize, p: Vec3) -> Mapping {
+        let (u, v) = match axis {
+            0 => (Vec3::Y, Vec3::Z),
+            1 => (Vec3::X, Vec3::Z),
+            _ => (Vec3::X, Vec3::Y),
+        };
+        (Vec2::new(p.dot(u), p.dot(v)), (u, v))
+    }
+
+    /// Same layout as the sphere's own uv, so the same maps fit
+    fn spherical(p: Vec3) -> Mapping {
+        let radius = p.length().max(f32::EPSILON);
+        let phi = p.y.atan2(p.x);
+        let theta = (p.z / radius).clamp(-1.0, 1.0).acos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        (
+            Vec2::new((phi + PI) / TAU, theta / PI),
+            (
+                TAU * radius * Vec3::new(-sin_phi * sin_theta, cos_phi * sin_theta, 0.0),
+                PI * radius * Vec3::new(cos_phi * cos_theta, sin_phi * cos_theta, -sin_theta),
+            ),
+        )
+    }
+
+    fn cylindrical(p: Vec3) -> Mapping {
+        let phi = p.y.atan2(p.x);
+        (Vec2::new((phi + PI) / TAU, p.z), (TAU * Vec3::new(-p.y, p.x, 0.0), Vec3::Z))
+    }
+
+    fn wraps_around(self) -> bool {
+        matches!(self, Self::Cylindrical | Self::Spherical)
+    }
+}
+
+/// A texture put on by projecting it from a shape instead of reading the surface's own uv, for meshes
+/// that have none like scanned STLs. The projection is done in a space set by the transform, which is
+/// world space by default. Objects are built straight in world space, so fitting the transform to an
+/// object's position and size gives object space.
+#[derive(Debug)]
+pub struct Projected {
+    texture: Arc<dyn Texture>,
+    projection: Projection,
+    /// World space to projection space
+    transform: Affine3A,
+    /// Back the other way, for the surface derivatives
+    inverse: Affine3A,
+    /// Takes normals into projection space
+    normal_matrix: Mat3A,
+}
+
+impl Projected {
+    pub fn new(texture: impl Texture + 'static, projection: Projection) -> Self {
+        Self {
+            texture: Arc::new(texture),
+            projection,
+            transform: Affine3A::IDENTITY,
+            inverse: Affine3A::IDENTITY,
+            normal_matrix: Mat3A::IDENTITY,
+        }
+    }
+
+    pub fn triplanar(texture: impl Texture + 'static, sharpness: f32) -> Self {
+        Self::new(texture, Projection::Triplanar { sharpness })
+    }
+
+    /// Moves the world into projection space, e.g. `Affine3A::from_scale_rotation_translation` inverted
+    /// to put the projection at an object's centre with one texture repeat across its size
+    pub fn with_transform(self, transform: Affine3A) -> Self {
+        Self {
+            transform,
+            inverse: transform.inverse(),
+            normal_matrix: transform.matrix3.inverse().transpose(),
+            ..self
+        }
+    }
+
+    /// Projection space centred on `centre` with the texture repeating once per `size` world units
+    pub fn around(self, centre: Vec3, size: f32) -> Self {
+        self.with_transform(Affine3A::from_scale(Vec3::splat(1.0 / size)) * Affine3A::from_translation(-centre))
+    }
+
+    /// Samples the texture as if the surface had been given the mapping's uv
+    fn sample_mapped(&self, hit: &Hit, map: impl Fn(Vec3) -> Mapping) -> Vec3Colour {
+        let p = self.transform.transform_point3(hit.impact);
+        let (uv, (dp_du, dp_dv)) = map(p);
+
+        // The pixel's uv footprint, stepping over the seam instead of all the way round
+        let wraps = self.projection.wraps_around();
+        let uv_step = |dp: Vec3| {
+            let step = map(p + self.transform.transform_vector3(dp)).0 - uv;
+            if wraps {
+                Vec2::new(step.x - step.x.round(), step.y)
+            } else {
+                step
+            }
+        };
+        let differentials = hit.differentials.map(|d| SurfaceDifferentials {
+            duv_dx: uv_step(d.dp_dx),
+            duv_dy: uv_step(d.dp_dy),
+            ..d
+        });
+
+        let hit = Hit {
+            uv,
+            uv_derivatives: (self.inverse.transform_vector3(dp_du), self.inverse.transform_vector3(dp_dv)),
+            differentials,
+            ..*hit
+        };
+        self.texture.sample(&hit)
+    }
+}
+
+impl Texture for Projected {
+    fn sample(&self, hit: &Hit) -> Vec3Colour {
+        match self.projection {
+            Projection::Planar => self.sample_mapped(hit, |p| Projection::planar_along(2, p)),
+            Projection::Cylindrical => self.sample_mapped(hit, Projection::cylindrical),
+            Projection::Spherical => self.sample_mapped(hit, Projection::spherical),
+            Projection::Triplanar { sharpness } => {
+                let normal = (self.normal_matrix * hit.original_normal).normalize_or_zero();
+                let weights = normal.abs().powf(sharpness.max(1.0));
+                let weights = weights / weights.element_sum().max(f32::EPSILON);
+                (0..3)
+                    .filter(|&axis| weights[axis] > 1e-3)
+                    .map(|axis| weights[axis] * self.sample_mapped(hit, |p| Projection::planar_along(axis, p)))
+                    .sum()
+            }
+        }
+    }
+}
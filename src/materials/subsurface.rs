@@ -0,0 +1,174 @@
+use crate::hit::Hit;
+use crate::materials::clear::Clear;
+use crate::materials::material::{tint_ray, RenderMaterial};
+use crate::utils::sample_henyey_greenstein;
+use crate::{Length, Ray, Vec3Colour};
+use glam::Vec3;
+use rand::random;
+
+/// Translucent material (skin, wax, marble, soap) where light enters the surface, takes a random walk
+/// through the inside and comes back out somewhere else.
+///
+/// The walk happens one segment at a time: whenever a ray inside the object reaches the surface, we
+/// check whether it would have scattered before getting there, and if so restart it from that point.
+/// Each scatter uses up a bounce, so these want a camera with plenty of bounces.
+///
+/// The surface is smooth glass with nothing absorbed inside, so it bends light and nests inside other
+/// dielectrics the same way `Clear` does.
+#[derive(Debug, Clone)]
+pub struct Subsurface {
+    /// Chance per channel of scattering rather than being absorbed at each interaction
+    albedo: Vec3Colour,
+    /// Average distance per channel travelled between interactions
+    mean_free_path: Vec3,
+    anisotropy: f32,
+    surface: Clear,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Vec3Colour, mean_free_path: Vec3, refractive_index: f32) -> Self {
+        Self {
+            albedo,
+            mean_free_path,
+            anisotropy: 0.0,
+            surface: Clear::new(Vec3Colour::ONE, refractive_index, 0.0),
+        }
+    }
+
+    /// The presets are measured in millimetres, this rescales them for scenes with other units
+    pub fn with_scale(self, scene_units_per_mm: f32) -> Self {
+        Self {
+            mean_free_path: self.mean_free_path * scene_units_per_mm,
+            ..self
+        }
+    }
+
+    // Skin and marble are from Jensen et al. 2001, "A Practical Model for Subsurface Light Transport",
+    // converted from reduced scattering and absorption coefficients into albedo and mean free path in mm.
+    // Wax and soap are eyeballed.
+
+    pub const SKIN: Self = Self {
+        albedo: Vec3::new(0.959, 0.838, 0.678),
+        mean_free_path: Vec3::new(1.295, 0.952, 0.671),
+        anisotropy: 0.0,
+        surface: Clear::new(Vec3Colour::ONE, 1.3, 0.0),
+    };
+    pub const MARBLE: Self = Self {
+        albedo: Vec3::new(0.999, 0.998, 0.998),
+        mean_free_path: Vec3::new(0.456, 0.381, 0.333),
+        anisotropy: 0.0,
+        surface: Clear::new(Vec3Colour::ONE, 1.5, 0.0),
+    };
+    pub const WAX: Self = Self {
+        albedo: Vec3::new(0.995, 0.98, 0.93),
+        mean_free_path: Vec3::new(2.0, 1.6, 1.1),
+        anisotropy: 0.0,
+        surface: Clear::new(Vec3Colour::ONE, 1.45, 0.0),
+    };
+    pub const SOAP: Self = Self {
+        albedo: Vec3::new(0.995, 0.99, 0.985),
+        mean_free_path: Vec3::new(3.0, 2.8, 2.6),
+        anisotropy: 0.0,
+        surface: Clear::new(Vec3Colour::ONE, 1.4, 0.0),
+    };
+
+    fn extinction(&self) -> Vec3 {
+        1.0 / self.mean_free_path.max(Vec3::splat(f32::EPSILON))
+    }
+
+    /// The ray travelled `distance` inside the object to reach the surface. Picks one colour channel to
+    /// sample a free-flight distance with, and weights every channel by how likely its own walk was compared
+    /// to the average over the channels, so differing path lengths per channel stay unbiased.
+    fn walk(&self, hit: Hit, distance: Length) -> Option<Ray> {
+        let extinction = self.extinction();
+        let channel = (random::<f32>() * 3.0) as usize % 3;
+        let flight = -(1.0 - random::<f32>()).ln() / extinction[channel];
+
+        if flight < distance {
+            let transmittance = (-extinction * flight).exp();
+            let pdf = (extinction * transmittance).element_sum() / 3.0;
+            let weight = self.albedo * extinction * transmittance / pdf;
+
+            let direction = sample_henyey_greenstein(hit.direction(), self.anisotropy);
+            Some(Ray::new(hit.ray.pos_at_length(flight), direction).with_attenuation(weight))
+        } else {
+            let transmittance = (-extinction * distance).exp();
+            let pdf = transmittance.element_sum() / 3.0;
+            let weight = transmittance / pdf;
+
+            let ray = self.surface.scatter_ray(hit)?;
+            Some(tint_ray(ray, weight, &hit.ray))
+        }
+    }
+}
+
+impl RenderMaterial for Subsurface {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        if hit.on_outside() {
+            self.surface.scatter_ray(hit)
+        } else {
+            let distance = hit.impact.distance(hit.ray.start());
+            self.walk(hit, distance)
+        }
+    }
+
+    fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        future_colour
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+
+    /// Where a ray leaves the unit sphere at the origin, as a hit the material can scatter
+    fn leaving_unit_sphere(ray: Ray) -> Hit {
+        let b = ray.start().dot(ray.direction());
+        let c = ray.start().length_squared() - 1.0;
+        let impact = ray.pos_at_length(-b + (b * b - c).max(0.0).sqrt());
+        Hit {
+            ray,
+            impact,
+            normal: -impact,
+            original_normal: impact,
+            uv: Vec2::ZERO,
+            uv_derivatives: (Vec3::X, Vec3::Y),
+            surface_colour: None,
+            differentials: None,
+        }
+    }
+
+    /// Follows light in through the bottom of a unit sphere until it comes back out, returning how much of
+    /// it is left per channel, or `None` if it never made it out
+    fn walk_through_sphere(material: &Subsurface) -> Option<Vec3Colour> {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::Z);
+        let mut hit = Hit {
+            impact: Vec3::NEG_Z,
+            normal: Vec3::NEG_Z,
+            original_normal: Vec3::NEG_Z,
+            ..leaving_unit_sphere(ray)
+        };
+        let mut throughput = Vec3Colour::ONE;
+        for _ in 0..100_000 {
+            let ray = material.scatter_ray(hit)?.inheriting(hit.ray);
+            throughput *= ray.attenuation();
+            if ray.start() == hit.impact && ray.direction().dot(hit.original_normal) > 0.0 {
+                assert!(!ray.media().contains(material.surface.entry().id), "left the sphere but not its medium");
+                return Some(throughput);
+            }
+            assert!(ray.media().contains(material.surface.entry().id), "inside the sphere but not its medium");
+            hit = leaving_unit_sphere(ray);
+        }
+        None
+    }
+
+    #[test]
+    fn walks_leave_and_keep_all_the_light_without_absorption() {
+        let material = Subsurface::new(Vec3Colour::ONE, Vec3::splat(0.1), 1.3);
+        for _ in 0..200 {
+            let throughput = walk_through_sphere(&material).expect("the walk never left the sphere");
+            assert!((throughput - Vec3Colour::ONE).abs().max_element() < 1e-2, "{throughput}");
+        }
+    }
+}
@@ -1,88 +1,442 @@
-use crate::hit::Hit;
-use crate::materials::material::RenderMaterial;
-use crate::utils::{bounce_across_normal, build_orthonormal_basis, random_point_on_unit_sphere, ColourChange};
-use crate::{Ray, Vec3Colour};
-use glam::{Mat3, UVec2, Vec2, Vec3};
-use image::Rgb32FImage;
+use crate::hit::{Hit, SurfaceDifferentials};
+use crate::utils::{dprintln, invalid_data, ColourChange};
+use crate::{Angle, Vec3Colour};
+use glam::{Mat2, Vec2, Vec3};
+use image::{Rgb, Rgb32FImage};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, Weak};
 
-#[derive(Debug)]
-pub struct Texture {
-    texture: ImageHolder,
-    normals: Option<ImageHolder>,
-    normal_strength: f32,
-    scale: Vec2,
-    roughness: f32,
-    // rotation: f32
-}
-
-impl Texture {
-    pub fn new(
-        image_path: impl AsRef<std::path::Path>,
-        normals_path: Option<impl AsRef<std::path::Path>>,
-        scale: Vec2,
-        roughness: f32,
-    ) -> Self {
+/// Anything giving a value at each point of a surface, to drive a material input like albedo or roughness.
+/// Plain colours and numbers are textures too, so a material can take either.
+pub trait Texture: Debug + Sync + Send {
+    fn sample(&self, hit: &Hit) -> Vec3Colour;
+
+    /// For inputs that are a single number, like roughness, this is the first channel
+    fn sample_scalar(&self, hit: &Hit) -> f32 {
+        self.sample(hit).x
+    }
+
+    fn with_uv_transform(self, transform: UvTransform) -> Transformed
+    where
+        Self: Sized + 'static,
+    {
+        Transformed::new(self, transform)
+    }
+}
+
+impl Texture for Vec3 {
+    fn sample(&self, _hit: &Hit) -> Vec3Colour {
+        *self
+    }
+}
+
+impl Texture for f32 {
+    fn sample(&self, _hit: &Hit) -> Vec3Colour {
+        Vec3::splat(*self)
+    }
+}
+
+/// So one loaded texture can be shared between materials
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn sample(&self, hit: &Hit) -> Vec3Colour {
+        self.as_ref().sample(hit)
+    }
+}
+
+/// Scales, rotates then offsets texture coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct UvTransform {
+    pub scale: Vec2,
+    pub offset: Vec2,
+    /// Anticlockwise, in degrees
+    pub rotation: Angle,
+}
+
+impl UvTransform {
+    pub fn new(scale: Vec2, offset: Vec2, rotation: Angle) -> Self {
         Self {
-            texture: ImageHolder::new(image_path),
-            normals: normals_path.map(|x| ImageHolder::new(x)),
-            normal_strength: 10.0,
             scale,
-            roughness,
+            offset,
+            rotation,
         }
     }
 
-    fn sample_image(&self, uv: Vec2) -> Vec3Colour {
-        self.texture.sample(uv, self.scale)
+    /// Repeats the texture `scale` times across the surface
+    pub fn scaled(scale: Vec2) -> Self {
+        Self::new(scale, Vec2::ZERO, 0.0)
     }
 
-    fn sample_normals(&self, uv: Vec2) -> Option<Vec3Colour> {
-        self.normals.as_ref().map(|x| x.sample(uv, self.scale).map(|x| x * 2. - 1.))
+    fn matrix(&self) -> Mat2 {
+        Mat2::from_angle(self.rotation.to_radians()) * Mat2::from_diagonal(self.scale)
     }
-    fn get_sampled_normal(&self, hit: Hit) -> Vec3 {
-        if let Some(sample_normal) = self.sample_normals(hit.uv) {
-            let (t, b, n) = (hit.uv_derivatives.0.normalize(), hit.uv_derivatives.1.normalize(), hit.normal);
-            let tbn = Mat3::from_cols(t, b, n);
 
-            let perturbed_normal = (tbn.transpose() * sample_normal).normalize();
-            // let final_normal = (hit.normal + self.normal_strength * perturbed_normal).normalize();
+    pub fn apply(&self, uv: Vec2) -> Vec2 {
+        self.matrix() * uv + self.offset
+    }
 
-            perturbed_normal
-        } else {
-            hit.normal
+    /// How the surface moves with the transformed coordinates, given how it moves with the original ones
+    pub fn derivatives(&self, (du, dv): (Vec3, Vec3)) -> (Vec3, Vec3) {
+        let inverse = self.matrix().inverse();
+        (
+            du * inverse.x_axis.x + dv * inverse.x_axis.y,
+            du * inverse.y_axis.x + dv * inverse.y_axis.y,
+        )
+    }
+
+    pub fn differentials(&self, differentials: SurfaceDifferentials) -> SurfaceDifferentials {
+        SurfaceDifferentials {
+            duv_dx: self.matrix() * differentials.duv_dx,
+            duv_dy: self.matrix() * differentials.duv_dy,
+            ..differentials
         }
     }
 }
 
-impl RenderMaterial for Texture {
-    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
-        let normal = self.get_sampled_normal(hit);
-        let new_dir = bounce_across_normal(hit.ray.direction(), normal);
-        let dir = new_dir + random_point_on_unit_sphere() * self.roughness;
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self::scaled(Vec2::ONE)
+    }
+}
+
+/// A texture looked up with transformed coordinates
+#[derive(Debug)]
+pub struct Transformed {
+    texture: Box<dyn Texture>,
+    transform: UvTransform,
+}
 
-        Some(Ray::new(hit.impact, dir))
+impl Transformed {
+    pub fn new(texture: impl Texture + 'static, transform: UvTransform) -> Self {
+        Self {
+            texture: Box::new(texture),
+            transform,
+        }
     }
+}
 
-    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
-        self.sample_image(hit.uv) * future_colour
+impl Texture for Transformed {
+    fn sample(&self, hit: &Hit) -> Vec3Colour {
+        let hit = Hit {
+            uv: self.transform.apply(hit.uv),
+            uv_derivatives: self.transform.derivatives(hit.uv_derivatives),
+            differentials: hit.differentials.map(|x| self.transform.differentials(x)),
+            ..*hit
+        };
+        self.texture.sample(&hit)
     }
 }
 
-#[derive(Debug)]
-struct ImageHolder {
-    image: Rgb32FImage,
-    size: Vec2,
-}
-
-impl ImageHolder {
-    fn new(path: impl AsRef<std::path::Path>) -> Self {
-        let image = image::open(path).unwrap().to_rgb32f();
-        let size = UVec2::new(image.width(), image.height()).as_vec2();
-        Self { image, size }
-    }
-    fn sample(&self, uv: Vec2, scale: Vec2) -> Vec3Colour {
-        let uv = (uv * self.size * scale) % self.size;
-        let uv = uv.as_uvec2();
-        let pixel = self.image.get_pixel(uv.x, uv.y);
-        pixel.to_vec3()
+/// What an image does outside of [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    /// Repeats, flipping every other tile so the edges line up
+    Mirror,
+    /// Stretches the edge pixels out
+    Clamp,
+    /// A flat colour all around the image
+    Border(Vec3Colour),
+}
+
+impl WrapMode {
+    /// The pixel index to read for `i` in an image `size` pixels across, `None` for the border
+    fn wrap(self, i: i32, size: u32) -> Option<u32> {
+        let size = size as i32;
+        let i = match self {
+            Self::Repeat => i.rem_euclid(size),
+            Self::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size { i } else { 2 * size - 1 - i }
+            }
+            Self::Clamp => i.clamp(0, size - 1),
+            Self::Border(_) => {
+                if !(0..size).contains(&i) {
+                    return None;
+                }
+                i
+            }
+        };
+        Some(i as u32)
+    }
+}
+
+/// How pixels are blended between their centres
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Blocky up close
+    Nearest,
+    Bilinear,
+    /// Catmull-Rom, sharper than bilinear without the blockiness
+    Bicubic,
+}
+
+/// How the numbers in an image file relate to light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColourSpace {
+    /// Gamma encoded colour, how most 8 bit photos and albedo maps are saved
+    Srgb,
+    /// Colour that's already proportional to light, like Radiance HDR and OpenEXR images
+    Linear,
+    /// Numbers that aren't colours at all, like normals, roughness and heights, read as they are
+    Data,
+}
+
+impl ColourSpace {
+    /// Floating point formats hold linear light, anything else is taken to be sRGB
+    fn guess(path: &Path) -> Self {
+        let extension = path.extension().and_then(|x| x.to_str()).map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("hdr" | "exr") => Self::Linear,
+            _ => Self::Srgb,
+        }
+    }
+}
+
+/// Which part of an image file a texture was made from and how it was read, so the cache can tell them apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Channels {
+    Colour(ColourSpace),
+    Alpha,
+}
+
+type MipLevels = Arc<Vec<Rgb32FImage>>;
+type ImageCache = HashMap<(PathBuf, Channels), Weak<Vec<Rgb32FImage>>>;
+
+/// Decoded images by file, so every texture made from the same file shares one copy. Only weakly held,
+/// an image is freed once the last texture using it is dropped.
+static IMAGE_CACHE: OnceLock<Mutex<ImageCache>> = OnceLock::new();
+
+/// An image file wrapped over the surface's uv coordinates.
+///
+/// The image is kept as a mipmap pyramid of halved copies, and each lookup reads the two levels closest
+/// to the size of the ray's footprint on the texture and blends them (trilinear filtering), so distant
+/// textures don't shimmer.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    /// Linear colour, the full size image first
+    levels: MipLevels,
+    /// Whether to read the smaller levels at all
+    mipmapped: bool,
+    filter: Filter,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    /// Loads an image, or reuses it if another texture already has. Missing and corrupt files are errors.
+    /// `.hdr` and `.exr` files are read as linear and everything else as sRGB, use `new_with_colour_space`
+    /// for maps that hold data rather than colour.
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new_with_colour_space(path.as_ref(), ColourSpace::guess(path.as_ref()))
+    }
+
+    /// Pixels are read at full precision, so 16 bit PNGs and TIFFs and floating point images keep every
+    /// level they have, and HDR values above 1 are kept
+    pub fn new_with_colour_space(path: impl AsRef<Path>, colour_space: ColourSpace) -> io::Result<Self> {
+        let levels = Self::load_cached(path.as_ref(), Channels::Colour(colour_space), |image| {
+            let mut image = image.to_rgb32f();
+            if colour_space == ColourSpace::Srgb {
+                image.pixels_mut().for_each(|pixel| *pixel = Rgb(pixel.to_vec3().to_array()));
+            }
+            image
+        })?;
+        Ok(Self::from_levels(levels))
+    }
+
+    /// Just the image's alpha channel, in every channel. For cutouts kept in the colour image.
+    pub fn new_alpha(path: impl AsRef<Path>) -> io::Result<Self> {
+        let levels = Self::load_cached(path.as_ref(), Channels::Alpha, |image| {
+            let image = image.to_rgba32f();
+            Rgb32FImage::from_fn(image.width(), image.height(), |x, y| Rgb([image.get_pixel(x, y)[3]; 3]))
+        })?;
+        Ok(Self::from_levels(levels))
+    }
+
+    /// Like `new`, but a file that won't load shows up as `missing` instead, so a preview render still goes
+    /// ahead. Only for colour, the checker makes no sense as normals or roughness.
+    pub fn new_or_missing(path: impl AsRef<Path>) -> Self {
+        Self::new(path).unwrap_or_else(|err| {
+            dprintln!("{err}, using the missing texture checker");
+            Self::missing()
+        })
+    }
+
+    /// Pink and black checks, the usual stand in for a texture that couldn't be found
+    pub fn missing() -> Self {
+        const PINK: Rgb<f32> = Rgb([1.0, 0.0, 1.0]);
+        let checker = Rgb32FImage::from_fn(8, 8, |x, y| if (x + y) % 2 == 0 { PINK } else { Rgb([0.0; 3]) });
+        Self::from_levels(Arc::new(vec![checker]))
+            .with_filter(Filter::Nearest)
+            .without_mipmaps()
+    }
+
+    fn load_cached(
+        path: &Path,
+        channels: Channels,
+        convert: impl FnOnce(image::DynamicImage) -> Rgb32FImage,
+    ) -> io::Result<MipLevels> {
+        let with_path = |err: io::Error| io::Error::new(err.kind(), format!("couldn't load {}: {err}", path.display()));
+        let key = (path.canonicalize().map_err(with_path)?, channels);
+
+        // A decoder that panicked can't have left the map half changed, so carry on past the poisoning
+        let cache = || IMAGE_CACHE.get_or_init(Default::default).lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(levels) = cache().get(&key).and_then(Weak::upgrade) {
+            return Ok(levels);
+        }
+
+        // Decoded without holding the lock, so different images load in parallel
+        let image = image::open(path).map_err(|err| with_path(invalid_data(err.to_string())))?;
+        let levels = Arc::new(Self::mipmaps(convert(image)));
+
+        let mut cache = cache();
+        // Someone else may have loaded the same image meanwhile, keep theirs so there's still only one copy
+        if let Some(existing) = cache.get(&key).and_then(Weak::upgrade) {
+            return Ok(existing);
+        }
+        cache.retain(|_, image| image.strong_count() > 0);
+        cache.insert(key, Arc::downgrade(&levels));
+        Ok(levels)
+    }
+
+    fn from_levels(levels: MipLevels) -> Self {
+        Self {
+            levels,
+            mipmapped: true,
+            filter: Filter::Bilinear,
+            wrap: WrapMode::Repeat,
+        }
+    }
+
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Self { filter, ..self }
+    }
+
+    pub fn with_wrap(self, wrap: WrapMode) -> Self {
+        Self { wrap, ..self }
+    }
+
+    /// Always reads the full size image, for data that shouldn't be blurred
+    pub fn without_mipmaps(self) -> Self {
+        Self {
+            mipmapped: false,
+            ..self
+        }
+    }
+
+    /// Halves the image down to a single pixel, averaging each 2x2 block. Odd sizes round up, the blocks
+    /// on the far edges repeating the last row or column, so no pixel is left out.
+    fn mipmaps(image: Rgb32FImage) -> Vec<Rgb32FImage> {
+        let mut levels = vec![image];
+        while let Some(last) = levels.last().filter(|x| x.width() > 1 || x.height() > 1) {
+            let (width, height) = (last.width().div_ceil(2), last.height().div_ceil(2));
+            let read = |x: u32, y: u32| last.get_pixel(x.min(last.width() - 1), y.min(last.height() - 1)).0;
+            let next = Rgb32FImage::from_fn(width, height, |x, y| {
+                let block = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| Vec3::from(read(2 * x + dx, 2 * y + dy)));
+                Rgb((block.iter().sum::<Vec3>() / 4.0).to_array())
+            });
+            levels.push(next);
+        }
+        levels
+    }
+
+    fn texel(&self, level: &Rgb32FImage, x: i32, y: i32) -> Vec3Colour {
+        let wrapped = self.wrap.wrap(x, level.width()).zip(self.wrap.wrap(y, level.height()));
+        match (wrapped, self.wrap) {
+            (Some((x, y)), _) => Vec3::from(level.get_pixel(x, y).0),
+            (None, WrapMode::Border(colour)) => colour,
+            (None, _) => unreachable!("only the border leaves the image"),
+        }
+    }
+
+    fn sample_level(&self, level: usize, uv: Vec2) -> Vec3Colour {
+        let level = &self.levels[level];
+        // Pixel centres are at half integers
+        let at = uv * Vec2::new(level.width() as f32, level.height() as f32) - 0.5;
+        let base = at.floor();
+        let t = at - base;
+        let (x, y) = (base.x as i32, base.y as i32);
+
+        match self.filter {
+            Filter::Nearest => {
+                let nearest = (at + 0.5).floor();
+                self.texel(level, nearest.x as i32, nearest.y as i32)
+            }
+            Filter::Bilinear => {
+                let top = self.texel(level, x, y).lerp(self.texel(level, x + 1, y), t.x);
+                let bottom = self.texel(level, x, y + 1).lerp(self.texel(level, x + 1, y + 1), t.x);
+                top.lerp(bottom, t.y)
+            }
+            Filter::Bicubic => {
+                let (wx, wy) = (catmull_rom_weights(t.x), catmull_rom_weights(t.y));
+                (0..4)
+                    .flat_map(|j| (0..4).map(move |i| (i, j)))
+                    .map(|(i, j)| self.texel(level, x + i - 1, y + j - 1) * wx[i as usize] * wy[j as usize])
+                    .sum()
+            }
+        }
+    }
+
+    /// The full size image at `uv`
+    pub fn sample_uv(&self, uv: Vec2) -> Vec3Colour {
+        self.sample_level(0, uv)
+    }
+
+    /// Blends between mipmap levels, `lod` 0 being the full image and each level up half the size
+    pub fn sample_lod(&self, uv: Vec2, lod: f32) -> Vec3Colour {
+        let top = if self.mipmapped { self.levels.len() - 1 } else { 0 };
+        let lod = lod.clamp(0.0, top as f32);
+        let below = lod.floor() as usize;
+        let above = (below + 1).min(top);
+        self.sample_level(below, uv).lerp(self.sample_level(above, uv), lod.fract())
+    }
+
+    /// The level where one pixel is about the size of a footprint of `uv_footprint` on the texture. Goes by
+    /// the footprint's long side, so nothing it covers is skipped over: footprints stretched out at glancing
+    /// angles come out a little blurry rather than aliasing. Poles, where one side blows up, are kept in
+    /// check by `Hit::uv_footprint`.
+    fn lod(&self, uv_footprint: Vec2) -> f32 {
+        let full = &self.levels[0];
+        let pixels = uv_footprint * Vec2::new(full.width() as f32, full.height() as f32);
+        pixels.max_element().max(1.0).log2()
+    }
+}
+
+/// Weights of the four pixels around a point `t` of the way between the middle two
+fn catmull_rom_weights(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+impl Texture for ImageTexture {
+    fn sample(&self, hit: &Hit) -> Vec3Colour {
+        self.sample_lod(hit.uv, self.lod(hit.uv_footprint()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn odd_sized_mipmaps_keep_the_last_row_and_column() {
+        // 3x3 of black with a white last row and column
+        let image = Rgb32FImage::from_fn(3, 3, |x, y| Rgb([if x == 2 || y == 2 { 1.0 } else { 0.0 }; 3]));
+        let levels = ImageTexture::mipmaps(image);
+        let sizes = levels.iter().map(|x| x.dimensions()).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(3, 3), (2, 2), (1, 1)]);
+
+        let pixel = |level: usize, x: u32, y: u32| levels[level].get_pixel(x, y).0[0];
+        assert_eq!(pixel(1, 0, 0), 0.0);
+        assert_eq!(pixel(1, 1, 0), 1.0);
+        assert_eq!(pixel(1, 0, 1), 1.0);
+        assert_eq!(pixel(1, 1, 1), 1.0);
+        assert_eq!(pixel(2, 0, 0), 0.75);
     }
 }
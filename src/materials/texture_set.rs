@@ -0,0 +1,257 @@
+use crate::hit::Hit;
+use crate::materials::material::RenderMaterial;
+use crate::materials::normal_map::{NormalConvention, NormalMap, SurfaceDetail};
+use crate::materials::principled::Principled;
+use crate::materials::texture::{ColourSpace, ImageTexture, Texture};
+use crate::utils::invalid_data;
+use crate::{Ray, Vec3Colour};
+use glam::Vec3;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A material made from a full set of PBR textures: albedo, normal, roughness, metallic, ambient occlusion
+/// and opacity. Shades as a `Principled` with its inputs read from the textures at each hit.
+///
+/// Where the opacity is below the cutoff the surface isn't there at all, so leaves and fences get holes.
+#[derive(Debug)]
+pub struct TextureSet {
+    albedo: Arc<dyn Texture>,
+    normal: Option<NormalMap>,
+    roughness: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    /// Darkens crevices the geometry is too coarse to shadow itself, only in the diffuse light
+    occlusion: Option<Arc<dyn Texture>>,
+    opacity: Option<Arc<dyn Texture>>,
+    alpha_cutoff: f32,
+}
+
+/// Which map a file in a texture set directory is, from words in its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MapKind {
+    Albedo,
+    Normal(NormalConvention),
+    Roughness,
+    Metallic,
+    Occlusion,
+    Opacity,
+}
+
+impl MapKind {
+    const IMAGE_EXTENSIONS: [&'static str; 10] = ["png", "jpg", "jpeg", "tga", "tif", "tiff", "bmp", "webp", "hdr", "exr"];
+
+    /// Names are split into words on `_`, `-`, `.` and spaces, e.g. `bark_04_Roughness_2k.png`. The map is
+    /// named by its suffix, the last word that isn't a resolution, a number or a normal convention, since
+    /// the set's own name comes first and can hold map words too (`metal_plate_ao_2k.jpg` is an occlusion
+    /// map, not a metallic one).
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        if !Self::IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?.to_lowercase();
+        let words = stem.split(['_', '-', '.', ' ']).collect::<Vec<_>>();
+
+        let is_resolution = |word: &str| {
+            let digits = word.strip_suffix('k').unwrap_or(word);
+            !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+        };
+        let is_convention = |word: &str| ["gl", "dx", "opengl", "directx"].contains(&word);
+        let suffix = words
+            .iter()
+            .rposition(|word| !word.is_empty() && !is_resolution(word) && !is_convention(word))?;
+
+        match Self::from_word(words[suffix])? {
+            // The convention is written after the map word, as in `nor_dx` or `NormalDX`
+            Self::Normal(_) if words[suffix..].iter().any(|word| ["dx", "directx", "normaldx"].contains(word)) => {
+                Some(Self::Normal(NormalConvention::DirectX))
+            }
+            kind => Some(kind),
+        }
+    }
+
+    /// The map a single lowercase word names, if any. Normal maps default to OpenGL style.
+    fn from_word(word: &str) -> Option<Self> {
+        match word {
+            "normal" | "nor" | "nrm" | "normalgl" | "normaldx" => Some(Self::Normal(NormalConvention::OpenGl)),
+            "albedo" | "basecolor" | "basecolour" | "diffuse" | "diff" | "color" | "colour" | "col" => {
+                Some(Self::Albedo)
+            }
+            "roughness" | "rough" | "rgh" => Some(Self::Roughness),
+            "metallic" | "metalness" | "metal" | "mtl" => Some(Self::Metallic),
+            "ao" | "occlusion" | "ambientocclusion" => Some(Self::Occlusion),
+            "opacity" | "alpha" | "mask" | "cutout" => Some(Self::Opacity),
+            _ => None,
+        }
+    }
+}
+
+impl TextureSet {
+    pub fn new(albedo: impl Texture + 'static) -> Self {
+        Self {
+            albedo: Arc::new(albedo),
+            normal: None,
+            roughness: Arc::new(0.5),
+            metallic: Arc::new(0.0),
+            occlusion: None,
+            opacity: None,
+            alpha_cutoff: 0.5,
+        }
+    }
+
+    /// Picks the maps out of a directory by the usual words at the end of their names (`albedo`/`basecolor`/
+    /// `diffuse`, `normal`, `roughness`, `metallic`, `ao`, `opacity`/`alpha`). Only the albedo has to be there.
+    /// Normal maps ending in `dx` are taken to be DirectX style. Everything but the albedo is read
+    /// as raw data, not sRGB colour.
+    pub fn from_directory(directory: impl AsRef<Path>) -> io::Result<Self> {
+        let mut maps: Vec<(MapKind, PathBuf)> = vec![];
+        for entry in std::fs::read_dir(directory.as_ref())? {
+            let path = entry?.path();
+            if let Some(kind) = MapKind::from_path(&path) {
+                maps.push((kind, path));
+            }
+        }
+        // So the same files are picked every time when there's more than one of a kind
+        maps.sort_by(|a, b| a.1.cmp(&b.1));
+        let find = |wanted: fn(MapKind) -> bool| maps.iter().find(|(kind, _)| wanted(*kind));
+        let data = |path| ImageTexture::new_with_colour_space(path, ColourSpace::Data);
+
+        let (_, albedo) = find(|x| x == MapKind::Albedo).ok_or_else(|| {
+            invalid_data(format!("no albedo map in {}", directory.as_ref().display()))
+        })?;
+        let mut set = Self::new(ImageTexture::new(albedo)?);
+
+        if let Some((MapKind::Normal(convention), path)) = find(|x| matches!(x, MapKind::Normal(_))) {
+            set = set.with_normal_map(NormalMap::new(data(path)?).with_convention(*convention));
+        }
+        if let Some((_, path)) = find(|x| x == MapKind::Roughness) {
+            set = set.with_roughness(data(path)?);
+        }
+        if let Some((_, path)) = find(|x| x == MapKind::Metallic) {
+            set = set.with_metallic(data(path)?);
+        }
+        if let Some((_, path)) = find(|x| x == MapKind::Occlusion) {
+            set = set.with_occlusion(data(path)?);
+        }
+        if let Some((_, path)) = find(|x| x == MapKind::Opacity) {
+            set = set.with_opacity(data(path)?, 0.5);
+        }
+        Ok(set)
+    }
+
+    pub fn with_normal_map(self, normal: NormalMap) -> Self {
+        Self {
+            normal: Some(normal),
+            ..self
+        }
+    }
+
+    pub fn with_roughness(self, roughness: impl Texture + 'static) -> Self {
+        Self {
+            roughness: Arc::new(roughness),
+            ..self
+        }
+    }
+
+    pub fn with_metallic(self, metallic: impl Texture + 'static) -> Self {
+        Self {
+            metallic: Arc::new(metallic),
+            ..self
+        }
+    }
+
+    pub fn with_occlusion(self, occlusion: impl Texture + 'static) -> Self {
+        Self {
+            occlusion: Some(Arc::new(occlusion)),
+            ..self
+        }
+    }
+
+    /// Anywhere the opacity is below `alpha_cutoff` is a hole. Use `ImageTexture::new_alpha` when the
+    /// opacity is the albedo image's alpha channel.
+    pub fn with_opacity(self, opacity: impl Texture + 'static, alpha_cutoff: f32) -> Self {
+        Self {
+            opacity: Some(Arc::new(opacity)),
+            alpha_cutoff,
+            ..self
+        }
+    }
+
+    fn is_hole(&self, hit: &Hit) -> bool {
+        self.opacity
+            .as_ref()
+            .is_some_and(|opacity| opacity.sample_scalar(hit) < self.alpha_cutoff)
+    }
+
+    /// The principled material this set describes at the hit
+    fn principled_at(&self, hit: &Hit) -> Principled {
+        let base_colour = self.albedo.sample(hit);
+        let roughness = self.roughness.sample_scalar(hit);
+        let metallic = self.metallic.sample_scalar(hit);
+        let occlusion = self.occlusion.as_ref().map_or(Vec3::ONE, |occlusion| occlusion.sample(hit));
+        Principled::new(base_colour, Vec3::ZERO, 1.5, 0.0, roughness, metallic).with_occlusion(occlusion)
+    }
+}
+
+impl RenderMaterial for TextureSet {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        if self.is_hole(&hit) {
+            return Some(Ray::new(hit.impact, hit.direction()).with_differential(hit.ray.differential()));
+        }
+
+        let shading_hit = match &self.normal {
+            Some(normal) => normal.apply(hit),
+            None => hit,
+        };
+        self.principled_at(&hit).scatter_ray(shading_hit)
+    }
+
+    /// Holes and surface alike just pass on what comes in, the surface's colour is on the scattered ray
+    fn colour(&self, _hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        future_colour
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(name: &str) -> Option<MapKind> {
+        MapKind::from_path(Path::new(name))
+    }
+
+    #[test]
+    fn poly_haven_names() {
+        assert_eq!(kind("metal_plate_diff_2k.jpg"), Some(MapKind::Albedo));
+        assert_eq!(
+            kind("metal_plate_nor_gl_2k.exr"),
+            Some(MapKind::Normal(NormalConvention::OpenGl))
+        );
+        assert_eq!(
+            kind("metal_plate_nor_dx_2k.exr"),
+            Some(MapKind::Normal(NormalConvention::DirectX))
+        );
+        assert_eq!(kind("metal_plate_rough_2k.jpg"), Some(MapKind::Roughness));
+        assert_eq!(kind("metal_plate_metal_2k.jpg"), Some(MapKind::Metallic));
+        assert_eq!(kind("metal_plate_ao_2k.jpg"), Some(MapKind::Occlusion));
+        assert_eq!(kind("metal_grate_opacity_4k.png"), Some(MapKind::Opacity));
+        assert_eq!(kind("metal_plate_2k.png"), None);
+    }
+
+    #[test]
+    fn ambient_cg_names() {
+        assert_eq!(kind("Metal032_2K-JPG_Color.jpg"), Some(MapKind::Albedo));
+        assert_eq!(
+            kind("Metal032_2K-JPG_NormalGL.jpg"),
+            Some(MapKind::Normal(NormalConvention::OpenGl))
+        );
+        assert_eq!(
+            kind("Metal032_2K-JPG_NormalDX.jpg"),
+            Some(MapKind::Normal(NormalConvention::DirectX))
+        );
+        assert_eq!(kind("Metal032_2K-JPG_Roughness.jpg"), Some(MapKind::Roughness));
+        assert_eq!(kind("Metal032_2K-JPG_Metalness.jpg"), Some(MapKind::Metallic));
+        assert_eq!(kind("Metal032_2K-JPG_AmbientOcclusion.jpg"), Some(MapKind::Occlusion));
+        assert_eq!(kind("Metal032_2K-JPG_Opacity.jpg"), Some(MapKind::Opacity));
+    }
+}
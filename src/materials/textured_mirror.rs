@@ -0,0 +1,64 @@
+use crate::hit::Hit;
+use crate::materials::material::RenderMaterial;
+use crate::materials::normal_map::{NormalMap, SurfaceDetail};
+use crate::materials::texture::{ColourSpace, ImageTexture, Texture, UvTransform};
+use crate::utils::{bounce_across_normal, random_point_on_unit_sphere};
+use crate::{Ray, Vec3Colour};
+use glam::Vec2;
+use std::io;
+use std::sync::Arc;
+
+/// Fuzzy mirror tinted by an image, with an optional normal map.
+///
+/// This was `materials::texture::Texture` until that name went to the `Texture` trait. The trait and a type
+/// can't share a name in one module, so there's no alias and code using the old name needs updating.
+#[derive(Debug)]
+pub struct TexturedMirror {
+    texture: Arc<dyn Texture>,
+    normals: Option<NormalMap>,
+    roughness: f32,
+}
+
+impl TexturedMirror {
+    pub fn new(
+        image_path: impl AsRef<std::path::Path>,
+        normals_path: Option<impl AsRef<std::path::Path>>,
+        scale: Vec2,
+        roughness: f32,
+    ) -> io::Result<Self> {
+        let transform = UvTransform::scaled(scale);
+        let normals = match normals_path {
+            Some(path) => {
+                let normals = ImageTexture::new_with_colour_space(path, ColourSpace::Data)?;
+                Some(NormalMap::new(normals.with_uv_transform(transform)))
+            }
+            None => None,
+        };
+        Ok(Self {
+            texture: Arc::new(ImageTexture::new(image_path)?.with_uv_transform(transform)),
+            normals,
+            roughness,
+        })
+    }
+
+    fn get_sampled_normal(&self, hit: Hit) -> glam::Vec3 {
+        match &self.normals {
+            Some(normals) => normals.apply(hit).normal,
+            None => hit.normal,
+        }
+    }
+}
+
+impl RenderMaterial for TexturedMirror {
+    fn scatter_ray(&self, hit: Hit) -> Option<Ray> {
+        let normal = self.get_sampled_normal(hit);
+        let new_dir = bounce_across_normal(hit.ray.direction(), normal);
+        let dir = new_dir + random_point_on_unit_sphere() * self.roughness;
+
+        Some(Ray::new(hit.impact, dir))
+    }
+
+    fn colour(&self, hit: Hit, future_colour: Vec3Colour) -> Vec3Colour {
+        self.texture.sample(&hit) * future_colour
+    }
+}
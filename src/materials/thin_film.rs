@@ -0,0 +1,173 @@
+use crate::hit::Hit;
+use crate::materials::texture::Texture;
+use crate::spectrum::RGB_WAVELENGTHS;
+use crate::Ray;
+use glam::Vec3;
+use std::f32::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
+
+/// A transparent coating a few hundred nanometres thick, like soap, oil or a lens coating.
+/// Light reflecting off its top and bottom interferes, so the reflectance depends on wavelength and angle,
+/// which gives the iridescent colours.
+#[derive(Debug, Clone)]
+pub struct ThinFilm {
+    /// In nanometres
+    thickness: f32,
+    refractive_index: f32,
+    /// Scales the thickness across the surface
+    thickness_map: Option<Arc<dyn Texture>>,
+}
+
+impl ThinFilm {
+    pub fn new(thickness_nm: f32, refractive_index: f32) -> Self {
+        Self {
+            thickness: thickness_nm,
+            refractive_index,
+            thickness_map: None,
+        }
+    }
+
+    pub fn with_thickness_map(self, map: impl Texture + 'static) -> Self {
+        Self {
+            thickness_map: Some(Arc::new(map)),
+            ..self
+        }
+    }
+
+    pub const SOAP: Self = Self {
+        thickness: 380.0,
+        refractive_index: 1.33,
+        thickness_map: None,
+    };
+    pub const OIL: Self = Self {
+        thickness: 450.0,
+        refractive_index: 1.47,
+        thickness_map: None,
+    };
+    /// Quarter wave magnesium fluoride anti-reflective coating, tuned for green
+    pub const ANTI_REFLECTIVE: Self = Self {
+        thickness: 100.0,
+        refractive_index: 1.38,
+        thickness_map: None,
+    };
+
+    /// The wavelengths the channels of a ray's colour are at, picking representative ones outside of spectral mode
+    pub fn channel_wavelengths(ray: &Ray) -> Vec3 {
+        ray.wavelengths().map(|x| x.all()).unwrap_or(RGB_WAVELENGTHS)
+    }
+
+    fn thickness_at(&self, hit: &Hit) -> f32 {
+        match &self.thickness_map {
+            Some(map) => self.thickness * map.sample_scalar(hit),
+            None => self.thickness,
+        }
+    }
+
+    /// Reflectance per channel at the given wavelengths, for light arriving at `cos_i` from a medium with
+    /// refractive index `outer` onto a film over a substrate with complex refractive index `eta + i k`
+    /// (k is zero for dielectrics)
+    pub fn reflectance(&self, cos_i: f32, outer: f32, eta: Vec3, k: Vec3, wavelengths: Vec3, hit: &Hit) -> Vec3 {
+        let thickness = self.thickness_at(hit);
+        Vec3::from_array(
+            [0, 1, 2].map(|i| self.airy_reflectance(cos_i, outer, Complex::new(eta[i], k[i]), wavelengths[i], thickness)),
+        )
+    }
+
+    /// The reflectance of a single film from the two interfaces' amplitude coefficients, summed over all the
+    /// bounces inside the film, averaged over both polarisations
+    fn airy_reflectance(&self, cos_i: f32, outer: f32, substrate: Complex, wavelength: f32, thickness: f32) -> f32 {
+        let n1 = Complex::real(outer);
+        let n2 = Complex::real(self.refractive_index);
+        let n3 = substrate;
+
+        // Snell's law, n1 sin1 = n sin, gives every cosine from the first
+        let cos1 = Complex::real(cos_i.clamp(0.0, 1.0));
+        let sin1_sq = Complex::real(1.0 - cos_i * cos_i);
+        let cos_in = |n: Complex| (Complex::real(1.0) - sin1_sq * (n1 / n) * (n1 / n)).sqrt();
+        let (cos2, cos3) = (cos_in(n2), cos_in(n3));
+
+        let phase = Complex::real(4.0 * PI * thickness / wavelength) * n2 * cos2;
+        let shift = (Complex::i() * phase).exp();
+
+        let combine = |r12: Complex, r23: Complex| {
+            let r = (r12 + r23 * shift) / (Complex::real(1.0) + r12 * r23 * shift);
+            r.norm_sqr().min(1.0)
+        };
+        let s = |a: Complex, ca: Complex, b: Complex, cb: Complex| (a * ca - b * cb) / (a * ca + b * cb);
+        let p = |a: Complex, ca: Complex, b: Complex, cb: Complex| (b * ca - a * cb) / (b * ca + a * cb);
+
+        let rs = combine(s(n1, cos1, n2, cos2), s(n2, cos2, n3, cos3));
+        let rp = combine(p(n1, cos1, n2, cos2), p(n2, cos2, n3, cos3));
+        0.5 * (rs + rp)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    fn real(re: f32) -> Self {
+        Self::new(re, 0.0)
+    }
+
+    fn i() -> Self {
+        Self::new(0.0, 1.0)
+    }
+
+    fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn exp(self) -> Self {
+        let scale = self.re.exp();
+        Self::new(scale * self.im.cos(), scale * self.im.sin())
+    }
+
+    /// Principal square root, with a non-negative real part
+    fn sqrt(self) -> Self {
+        let modulus = self.norm_sqr().sqrt();
+        let re = ((modulus + self.re) * 0.5).max(0.0).sqrt();
+        let im = ((modulus - self.re) * 0.5).max(0.0).sqrt().copysign(self.im);
+        Self::new(re, im)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(self.re * rhs.re - self.im * rhs.im, self.re * rhs.im + self.im * rhs.re)
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        let denominator = rhs.norm_sqr();
+        Self::new(
+            (self.re * rhs.re + self.im * rhs.im) / denominator,
+            (self.im * rhs.re - self.re * rhs.im) / denominator,
+        )
+    }
+}
@@ -1,6 +1,10 @@
 use crate::intersections::intersection::RenderIntersection;
+use crate::intersections::volume::{GridVolume, Volume};
+use crate::intersections::voxel_grid::VoxelGrid;
 use crate::materials::material::RenderMaterial;
+use crate::materials::medium::{GridMedium, Medium};
 use std::fmt::Debug;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct RenderObject {
@@ -18,6 +22,22 @@ impl RenderObject {
             material: Box::new(material) as Box<dyn RenderMaterial>,
         }
     }
+    /// Fills a closed boundary (sphere, box, mesh) with a homogeneous medium
+    pub fn new_volume(boundary: impl RenderIntersection + 'static, medium: Medium) -> Self {
+        Self::new(Volume::new(boundary, medium.extinction()), medium)
+    }
+    /// Medium with varying density (and optionally emission) read from voxel grids,
+    /// the coefficients of `medium` are scaled by the density
+    pub fn new_grid_volume(
+        density: VoxelGrid,
+        emission: Option<VoxelGrid>,
+        emission_strength: f32,
+        medium: Medium,
+    ) -> Self {
+        let volume = GridVolume::new(Arc::new(density), medium.extinction());
+        let material = GridMedium::new(medium, emission.map(Arc::new), emission_strength);
+        Self::new(volume, material)
+    }
     pub fn boxed_new(
         intersector: Box<dyn RenderIntersection>,
         material: Box<dyn RenderMaterial>,
@@ -1,10 +1,38 @@
 use crate::*;
+use crate::spectrum::{Dispersion, Wavelengths};
 use glam::Vec3;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub(crate) start: Vec3,
     direction: Vec3,
+    /// Weight a material gives to this particular scattered ray, for materials whose colour depends on
+    /// which way they scattered. The scene multiplies the light coming back along the ray by it.
+    attenuation: Vec3Colour,
+    /// The attenuation was worked out at the wavelengths the colour channels are at (see `Wavelengths`),
+    /// so it shouldn't be treated as an RGB colour in spectral mode
+    attenuation_per_wavelength: bool,
+    /// Dielectrics the ray is currently inside, `None` until a material sets it, in which case the scene
+    /// carries over the stack of the ray this one was scattered from
+    media: Option<MediumStack>,
+    /// Only in spectral mode, when the colour channels hold values at these wavelengths instead of RGB
+    wavelengths: Option<Wavelengths>,
+    /// Width of the beam of light the ray stands for where it starts, and how fast it grows with distance.
+    /// Camera rays cover a pixel, so texture lookups further away can blur over more of the texture.
+    cone_width: f32,
+    cone_spread: f32,
+    /// Rays through the neighbouring pixels, followed through mirrors and glass so textures seen in them
+    /// are filtered right. Gone after anything rougher.
+    differential: Option<RayDifferential>,
+}
+
+/// Offset rays one pixel across and one pixel down from a ray (Igehy 1999, "Tracing Ray Differentials")
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
@@ -14,6 +42,25 @@ impl Ray {
     pub(crate) fn start(&self) -> Vec3 {
         self.start
     }
+    pub(crate) fn attenuation(&self) -> Vec3Colour {
+        self.attenuation
+    }
+    pub(crate) fn attenuation_per_wavelength(&self) -> bool {
+        self.attenuation_per_wavelength
+    }
+    pub(crate) fn media(&self) -> MediumStack {
+        self.media.unwrap_or_default()
+    }
+    pub(crate) fn wavelengths(&self) -> Option<Wavelengths> {
+        self.wavelengths
+    }
+    pub(crate) fn differential(&self) -> Option<RayDifferential> {
+        self.differential
+    }
+    /// Width of the ray's cone after travelling `distance`
+    pub(crate) fn width_at(&self, distance: f32) -> f32 {
+        self.cone_width + self.cone_spread * distance
+    }
 }
 
 impl Ray {
@@ -21,6 +68,13 @@ impl Ray {
         Ray {
             start,
             direction: direction.normalize(),
+            attenuation: Vec3Colour::ONE,
+            attenuation_per_wavelength: false,
+            media: None,
+            wavelengths: None,
+            cone_width: 0.0,
+            cone_spread: 0.0,
+            differential: None,
         }
     }
 
@@ -28,7 +82,231 @@ impl Ray {
         Self::new(from, to - from)
     }
 
+    pub fn with_attenuation(self, attenuation: Vec3Colour) -> Self {
+        Self {
+            attenuation,
+            ..self
+        }
+    }
+
+    /// An attenuation already at the ray's wavelengths, such as from thin-film interference
+    pub fn with_attenuation_per_wavelength(self, attenuation: Vec3) -> Self {
+        Self {
+            attenuation,
+            attenuation_per_wavelength: true,
+            ..self
+        }
+    }
+
+    pub fn with_media(self, media: MediumStack) -> Self {
+        Self {
+            media: Some(media),
+            ..self
+        }
+    }
+
+    pub fn with_wavelengths(self, wavelengths: Wavelengths) -> Self {
+        Self {
+            wavelengths: Some(wavelengths),
+            ..self
+        }
+    }
+
+    /// `spread` is in radians
+    pub fn with_cone(self, width: f32, spread: f32) -> Self {
+        Self {
+            cone_width: width,
+            cone_spread: spread,
+            ..self
+        }
+    }
+
+    /// The same ray heading another way, keeping its wavelengths, media, cone and differential
+    pub fn with_direction(self, direction: Vec3) -> Self {
+        Self {
+            direction: direction.normalize(),
+            ..self
+        }
+    }
+
+    /// `None` when the material can't follow the differential, e.g. it's too rough
+    pub fn with_differential(self, differential: Option<RayDifferential>) -> Self {
+        Self { differential, ..self }
+    }
+
+    /// Keeps the media and wavelengths this ray was given, otherwise takes them from the ray it was scattered from.
+    /// The cone carries on from where the parent's reached this ray's start.
+    pub(crate) fn inheriting(self, parent: Ray) -> Self {
+        Self {
+            media: self.media.or(parent.media),
+            wavelengths: self.wavelengths.or(parent.wavelengths),
+            cone_width: parent.width_at(parent.start.distance(self.start)),
+            cone_spread: parent.cone_spread,
+            ..self
+        }
+    }
+
     pub fn pos_at_length(&self, l: Length) -> Vec3 {
         self.start + self.direction * l
     }
 }
+
+/// One dielectric a ray is inside of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MediumEntry {
+    /// Identifies the object's material, so leaving it removes the right entry
+    pub id: usize,
+    pub refractive_index: f32,
+    /// Where objects overlap, the one with the highest priority is the medium actually there
+    pub priority: u32,
+    /// Fraction of each channel left after travelling one unit through it
+    pub transmittance: Vec3Colour,
+    pub dispersion: Option<Dispersion>,
+}
+
+impl MediumEntry {
+    /// The world outside of every object
+    pub const AIR: Self = Self {
+        id: 0,
+        refractive_index: 1.0,
+        priority: 0,
+        transmittance: Vec3Colour::ONE,
+        dispersion: None,
+    };
+
+    /// At the hero wavelength in spectral mode, if the medium disperses light
+    pub fn refractive_index_at(&self, wavelengths: Option<Wavelengths>) -> f32 {
+        match (self.dispersion, wavelengths) {
+            (Some(dispersion), Some(wavelengths)) => dispersion.refractive_index(wavelengths.hero()),
+            _ => self.refractive_index,
+        }
+    }
+}
+
+/// The nested dielectrics a ray is inside, e.g. an air bubble in an ice cube in water in a glass.
+/// Fixed size so rays stay `Copy`. Nesting deeper than this forgets the least important medium.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MediumStack {
+    entries: [Option<MediumEntry>; MediumStack::MAX_DEPTH],
+}
+
+impl MediumStack {
+    pub const MAX_DEPTH: usize = 8;
+
+    fn iter(&self) -> impl Iterator<Item = &MediumEntry> {
+        self.entries.iter().map_while(Option::as_ref)
+    }
+
+    /// The medium the ray is actually travelling through, the highest priority one, latest entered on ties
+    pub fn current(&self) -> MediumEntry {
+        self.iter()
+            .copied()
+            .reduce(|best, x| if x.priority >= best.priority { x } else { best })
+            .unwrap_or(MediumEntry::AIR)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.iter().any(|x| x.id == id)
+    }
+
+    /// Whether something the ray is inside takes precedence over a medium with this priority,
+    /// in which case the boundary of that medium isn't really there
+    pub fn overrides(&self, priority: u32) -> bool {
+        self.iter().any(|x| x.priority > priority)
+    }
+
+    pub fn pushed(self, entry: MediumEntry) -> Self {
+        debug_assert!(
+            self.iter().count() < Self::MAX_DEPTH,
+            "medium stack overflowed entering {entry:?}, media nested deeper than {} are forgotten",
+            Self::MAX_DEPTH
+        );
+        self.pushed_evicting(entry)
+    }
+
+    /// Pushes, and when the stack is full drops whichever entry (the new one included) has the lowest
+    /// priority, the oldest on ties. Leaving a dropped medium later then removes nothing, rather than
+    /// whatever else happened to share the slot.
+    fn pushed_evicting(mut self, entry: MediumEntry) -> Self {
+        if let Some(free) = self.entries.iter_mut().find(|x| x.is_none()) {
+            *free = Some(entry);
+            return self;
+        }
+        let (lowest, lowest_entry) = self
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by_key(|(_, x)| x.priority)
+            .expect("a full stack has entries");
+        if entry.priority >= lowest_entry.priority {
+            self.entries[lowest..].rotate_left(1);
+            self.entries[Self::MAX_DEPTH - 1] = Some(entry);
+        }
+        self
+    }
+
+    /// Without the most recent entry with this id
+    pub fn removed(mut self, id: usize) -> Self {
+        let len = self.iter().count();
+        if let Some(i) = self.entries[..len].iter().rposition(|x| x.is_some_and(|x| x.id == id)) {
+            self.entries[i..len].rotate_left(1);
+            self.entries[len - 1] = None;
+        }
+        self
+    }
+
+    /// Beer-Lambert absorption over a distance through the current medium
+    pub fn transmittance(&self, distance: Length) -> Vec3Colour {
+        self.current().transmittance.powf(distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn medium(id: usize, priority: u32) -> MediumEntry {
+        MediumEntry {
+            id,
+            refractive_index: 1.0 + id as f32 / 10.0,
+            priority,
+            ..MediumEntry::AIR
+        }
+    }
+
+    fn full_stack() -> MediumStack {
+        (1..=MediumStack::MAX_DEPTH).fold(MediumStack::default(), |stack, id| {
+            stack.pushed(medium(id, if id == 3 { 0 } else { 1 }))
+        })
+    }
+
+    #[test]
+    fn full_stack_forgets_the_lowest_priority_medium() {
+        let stack = full_stack().pushed_evicting(medium(9, 1));
+        assert!(!stack.contains(3));
+        assert!(stack.contains(9));
+        assert_eq!(stack.current(), medium(9, 1));
+
+        // Leaving what was forgotten leaves everything else alone
+        let left = stack.removed(3);
+        assert_eq!(left.iter().count(), MediumStack::MAX_DEPTH);
+        // And leaving the newest goes back to the one before
+        assert_eq!(stack.removed(9).current(), medium(8, 1));
+    }
+
+    #[test]
+    fn full_stack_ignores_a_less_important_medium() {
+        let stack = full_stack().removed(3).pushed(medium(3, 2));
+        let unchanged = stack.pushed_evicting(medium(9, 0));
+        assert!(!unchanged.contains(9));
+        assert_eq!(unchanged.iter().count(), MediumStack::MAX_DEPTH);
+        assert_eq!(unchanged.current(), medium(3, 2));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "medium stack overflowed")]
+    fn overflowing_is_caught_in_debug_builds() {
+        full_stack().pushed(medium(9, 1));
+    }
+}
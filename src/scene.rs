@@ -1,17 +1,23 @@
 use crate::hit::Hit;
+use crate::intersections::mesh::IndexedMesh;
+use crate::intersections::mesh_io::write_mesh;
+use crate::materials::material::RenderMaterial;
+use crate::materials::medium::Medium;
+use crate::spectrum::{self, Wavelengths};
 use crate::*;
 use glam::{UVec2, Vec2};
 use objects::RenderObject;
 use rand::random;
 
-use utils::dprintln;
-use crate::utils::vec_format;
-
 #[derive(Debug)]
 pub struct Scene {
     pub camera: Camera,
     pub background: fn(direction: Vec3, camera: &Camera) -> Vec3,
     pub objects: Vec<RenderObject>,
+    /// Atmospheric medium filling all of the space between objects
+    pub fog: Option<Medium>,
+    /// Trace wavelengths rather than RGB, for dispersion
+    pub spectral: bool,
 }
 
 impl Scene {
@@ -24,15 +30,49 @@ impl Scene {
             camera,
             background,
             objects,
+            fog: None,
+            spectral: false,
         }
     }
 
+    pub fn with_fog(mut self, fog: Medium) -> Self {
+        self.fog = Some(fog);
+        self
+    }
+
+    /// Slower and noisier, but needed for anything wavelength dependent like dispersion
+    pub fn with_spectral(mut self) -> Self {
+        self.spectral = true;
+        self
+    }
+
+    /// All the triangle based geometry in the scene as one mesh, other kinds of objects are left out
+    pub fn to_mesh(&self) -> IndexedMesh {
+        IndexedMesh::merge(
+            self.objects
+                .iter()
+                .filter_map(|object| object.intersector.to_mesh())
+                .collect(),
+        )
+    }
+
+    /// Writes the scene's triangles to an STL, OBJ or PLY file, picked by extension
+    pub fn save_geometry(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        write_mesh(path, &self.to_mesh())
+    }
+
     pub fn trace_from_image_prop(&self, image_prop: UVec2, image_dimensions: UVec2) -> Vec3 {
         let samples = self.camera.samples_per_pixel;
         (0..samples)
             .map(|_x| {
                 let ray = self.get_outgoing_ray(image_prop, image_dimensions);
-                self.trace(ray, self.camera.max_bounces)
+                if self.spectral {
+                    let wavelengths = Wavelengths::sample();
+                    let values = self.trace(ray.with_wavelengths(wavelengths), self.camera.max_bounces);
+                    spectrum::to_rgb(values, wavelengths)
+                } else {
+                    self.trace(ray, self.camera.max_bounces)
+                }
             })
             .sum::<Vec3>()
             / (self.camera.samples_per_pixel as f32)
@@ -43,15 +83,71 @@ impl Scene {
             return BLACK.to_vec3();
         }
 
-        if let Some((object, hit)) = self.intersect(ray, 0.001, None) {
-            let new_colour = object
-                .material
-                .scatter_ray(hit)
-                .map(|new_ray| self.trace(new_ray, depth - 1))
-                .unwrap_or(BLACK.to_vec3());
-            object.material.colour(hit, new_colour)
+        let closest = self.intersect(ray, 0.001, None);
+
+        if let Some(fog) = &self.fog {
+            let surface_distance = closest
+                .map(|(_, hit)| hit.impact.distance(ray.start()))
+                .unwrap_or(f32::INFINITY);
+            let distance = fog.sample_distance();
+            if distance < surface_distance {
+                // Glass the ray is inside absorbs on the way to the scattering point too
+                let absorbed = ray.media().transmittance(distance);
+                let hit = Hit::new_in_medium(ray.pos_at_length(distance), ray);
+                return self.shade(fog, hit, depth) * self.to_ray_space(absorbed, ray);
+            }
+        }
+
+        if let Some((object, hit)) = closest {
+            let absorbed = ray.media().transmittance(hit.impact.distance(ray.start()));
+            self.shade(object.material.as_ref(), hit, depth) * self.to_ray_space(absorbed, ray)
         } else {
-            (self.background)(ray.direction(), &self.camera)
+            self.to_ray_space((self.background)(ray.direction(), &self.camera), ray)
+        }
+    }
+
+    /// Light leaving a hit towards the ray that made it, following the scattered ray for the light coming in
+    fn shade(&self, material: &dyn RenderMaterial, hit: Hit, depth: u32) -> Vec3 {
+        let ray = hit.ray;
+        let incoming = material.scatter_ray(hit).map(|new_ray| {
+            let new_ray = new_ray.inheriting(ray);
+            let attenuation = if new_ray.attenuation_per_wavelength() {
+                new_ray.attenuation()
+            } else {
+                self.to_ray_space(new_ray.attenuation(), ray)
+            };
+            let mut incoming = self.trace(new_ray, depth - 1) * attenuation;
+            if Self::lost_secondary_wavelengths(ray, new_ray) {
+                incoming *= Wavelengths::termination_weight();
+            }
+            incoming
+        });
+        let incoming = incoming.unwrap_or(BLACK.to_vec3());
+
+        match ray.wavelengths() {
+            None => material.colour(hit, incoming),
+            // Materials work in RGB, but their colour is linear in what comes in: `a * incoming + e`.
+            // Splitting out `a` and `e` lets both be turned into spectra separately.
+            Some(wavelengths) => {
+                let emitted = material.colour(hit, Vec3::ZERO);
+                let albedo = material.colour(hit, Vec3::ONE) - emitted;
+                spectrum::upsample(albedo, wavelengths) * incoming + spectrum::upsample(emitted, wavelengths)
+            }
+        }
+    }
+
+    fn lost_secondary_wavelengths(ray: Ray, new_ray: Ray) -> bool {
+        match (ray.wavelengths(), new_ray.wavelengths()) {
+            (Some(before), Some(after)) => after.is_hero_only() && !before.is_hero_only(),
+            _ => false,
+        }
+    }
+
+    /// RGB colours are upsampled to the ray's wavelengths in spectral mode
+    fn to_ray_space(&self, colour: Vec3Colour, ray: Ray) -> Vec3 {
+        match ray.wavelengths() {
+            Some(wavelengths) => spectrum::upsample(colour, wavelengths),
+            None => colour,
         }
     }
 
@@ -69,23 +165,40 @@ impl Scene {
         // Note that y is inverted because screen coords typically go down but we want up in camera space.
         let image_prop = Vec2::new(image_prop.x - 0.5, 0.5 - image_prop.y);
 
-        let [x, y] = image_prop.to_array();
-
         // Compute aspect ratio based on image dimensions
         let aspect_ratio = image_dimensions.x as f32 / image_dimensions.y as f32;
 
         // Horizontal field of view
         let tan_fov = (self.camera.hoz_fov.to_radians() / 2.0).tan();
 
-        // Calculate horizontal and vertical offsets
-        let right_offset = self.camera.right() * (x * aspect_ratio * tan_fov);
-        let up_offset = self.camera.up() * (y * tan_fov);
+        let direction_through = |image_prop: Vec2| {
+            let [x, y] = image_prop.to_array();
+
+            // Calculate horizontal and vertical offsets
+            let right_offset = self.camera.right() * (x * aspect_ratio * tan_fov);
+            let up_offset = self.camera.up() * (y * tan_fov);
+
+            // Compute direction
+            (self.camera.forward() + right_offset + up_offset).normalize()
+        };
+        let direction = direction_through(image_prop);
+
+        // Rays through the next pixel across and down, for texture filtering
+        let pixel = Vec2::ONE / image_dimensions.as_vec2();
+        let differential = RayDifferential {
+            rx_origin: self.camera.location,
+            rx_direction: direction_through(image_prop + Vec2::new(pixel.x, 0.0)),
+            ry_origin: self.camera.location,
+            ry_direction: direction_through(image_prop - Vec2::new(0.0, pixel.y)),
+        };
 
-        // Compute direction
-        let direction = (self.camera.forward() + right_offset + up_offset).normalize();
+        // Angle one pixel covers, for the ray's cone
+        let pixel_spread = aspect_ratio * tan_fov / image_dimensions.x as f32;
 
         // Construct the ray with the camera's location as origin.
         Ray::new(self.camera.location, direction)
+            .with_cone(0.0, pixel_spread)
+            .with_differential(Some(differential))
     }
 
     // this shit needs optimising - O(n) for objects is mad slow
@@ -101,12 +214,12 @@ impl Scene {
             .flat_map(|shape| {
                 shape
                     .intersector
-                    .intersects(ray)
+                    .surface_hits(ray)
                     .into_iter()
                     .map(|x| (shape, x))
                     .collect::<Vec<_>>()
             })
-            .map(|(object, intersection)| (object, intersection, intersection.distance(ray.start)))
+            .map(|(object, surface)| (object, surface, surface.impact.distance(ray.start)))
             .filter(|(_, _, dist)| *dist >= min_distance)
             .filter(|(_, _, dist)| match max_distance {
                 None => true,
@@ -0,0 +1,194 @@
+//! Spectral rendering: wavelength sampling, RGB to spectrum upsampling and the conversion back to RGB at the film.
+//!
+//! In spectral mode a camera ray carries three wavelengths (hero-wavelength sampling, Wilkie et al. 2014),
+//! and the three channels of every colour along its path hold values at those wavelengths instead of RGB.
+
+use crate::Vec3Colour;
+use glam::{Mat3, Vec3};
+use rand::random;
+use std::sync::OnceLock;
+
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 720.0;
+const RANGE: f32 = MAX_WAVELENGTH - MIN_WAVELENGTH;
+
+/// Wavelengths in nm that stand in for the red, green and blue channels outside of spectral mode
+pub const RGB_WAVELENGTHS: Vec3 = Vec3::new(650.0, 550.0, 450.0);
+
+/// Reads a per channel quantity (like a metal's refractive index) at a wavelength by interpolating
+/// between the channels' representative wavelengths
+pub fn rgb_at_wavelength(values: Vec3, lambda: f32) -> f32 {
+    let [r, g, b] = RGB_WAVELENGTHS.to_array();
+    if lambda >= g {
+        let t = ((lambda - g) / (r - g)).min(1.0);
+        values.y + (values.x - values.y) * t
+    } else {
+        let t = ((g - lambda) / (g - b)).min(1.0);
+        values.y + (values.z - values.y) * t
+    }
+}
+
+/// The wavelengths in nanometres a path is carrying, the hero wavelength is the first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    lambda: Vec3,
+    /// Set once something wavelength dependent (dispersion) happened, from then on only the hero is valid
+    hero_only: bool,
+}
+
+impl Wavelengths {
+    /// A uniformly random hero with the other two spread evenly across the visible range
+    pub fn sample() -> Self {
+        let hero = MIN_WAVELENGTH + random::<f32>() * RANGE;
+        let rotate = |i: f32| MIN_WAVELENGTH + (hero - MIN_WAVELENGTH + i * RANGE / 3.0) % RANGE;
+        Self {
+            lambda: Vec3::new(hero, rotate(1.0), rotate(2.0)),
+            hero_only: false,
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda.x
+    }
+
+    pub fn all(&self) -> Vec3 {
+        self.lambda
+    }
+
+    pub fn is_hero_only(&self) -> bool {
+        self.hero_only
+    }
+
+    /// Drops the secondary wavelengths, after e.g. refraction sent each of them a different way
+    pub fn terminate_secondary(self) -> Self {
+        Self {
+            hero_only: true,
+            ..self
+        }
+    }
+
+    /// Weight for a path that lost its secondary wavelengths partway, the hero now stands in for all three
+    pub fn termination_weight() -> Vec3 {
+        Vec3::new(3.0, 0.0, 0.0)
+    }
+}
+
+// Smits 1999, "An RGB-to-Spectrum Conversion for Reflectances", 10 bins evenly spread over 380-720nm
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Linear interpolation between the bin centres
+fn smits_basis(table: &[f32; 10], lambda: f32) -> f32 {
+    let x = ((lambda - MIN_WAVELENGTH) / RANGE * 10.0 - 0.5).clamp(0.0, 9.0);
+    let i = (x as usize).min(8);
+    let t = x - i as f32;
+    table[i] * (1.0 - t) + table[i + 1] * t
+}
+
+/// Value at one wavelength of a smooth spectrum with the given linear RGB colour
+fn upsample_one(rgb: Vec3Colour, lambda: f32) -> f32 {
+    let basis = |table| smits_basis(table, lambda);
+    let [r, g, b] = rgb.to_array();
+    if r <= g && r <= b {
+        r * basis(&SMITS_WHITE)
+            + if g <= b {
+                (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+            } else {
+                (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * basis(&SMITS_WHITE)
+            + if r <= b {
+                (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+            } else {
+                (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+            }
+    } else {
+        b * basis(&SMITS_WHITE)
+            + if r <= g {
+                (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+            } else {
+                (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+            }
+    }
+}
+
+/// Values at the path's wavelengths of a smooth spectrum with the given linear RGB colour
+pub fn upsample(rgb: Vec3Colour, wavelengths: Wavelengths) -> Vec3 {
+    let lambda = wavelengths.all();
+    Vec3::new(
+        upsample_one(rgb, lambda.x),
+        upsample_one(rgb, lambda.y),
+        upsample_one(rgb, lambda.z),
+    )
+}
+
+/// Piecewise Gaussian fit of the CIE 1931 colour matching functions.
+/// Wyman, Sloan and Shirley 2013, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+pub fn colour_matching(lambda: f32) -> Vec3 {
+    let g = |mu: f32, below: f32, above: f32| {
+        let sigma = if lambda < mu { below } else { above };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// CIE XYZ to linear sRGB (D65)
+const XYZ_TO_SRGB: Mat3 = Mat3::from_cols(
+    Vec3::new(3.2406, -0.9689, 0.0557),
+    Vec3::new(-1.5372, 1.8758, -0.2040),
+    Vec3::new(-0.4986, 0.0415, 1.0570),
+);
+
+/// Linear sRGB of the flat spectrum, used to white balance the film so a white surface stays white
+fn flat_spectrum_rgb() -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let xyz = (MIN_WAVELENGTH as u32..MAX_WAVELENGTH as u32)
+            .map(|lambda| colour_matching(lambda as f32 + 0.5))
+            .sum::<Vec3>();
+        XYZ_TO_SRGB * xyz
+    })
+}
+
+/// Converts one path's values at its wavelengths into linear sRGB
+pub fn to_rgb(values: Vec3, wavelengths: Wavelengths) -> Vec3Colour {
+    let lambda = wavelengths.all();
+    let xyz = values.x * colour_matching(lambda.x)
+        + values.y * colour_matching(lambda.y)
+        + values.z * colour_matching(lambda.z);
+    // Each wavelength was picked with density 1 / RANGE, and there are three of them
+    let xyz = xyz * RANGE / 3.0;
+    XYZ_TO_SRGB * xyz / flat_spectrum_rgb()
+}
+
+/// How a dielectric's refractive index changes with wavelength
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    /// n = a + b / λ², with λ in micrometres
+    Cauchy { a: f32, b: f32 },
+    /// n² = 1 + Σ bᵢ λ² / (λ² - cᵢ), with λ in micrometres
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub fn refractive_index(&self, wavelength_nm: f32) -> f32 {
+        let l2 = (wavelength_nm / 1000.0).powi(2);
+        match self {
+            Self::Cauchy { a, b } => a + b / l2,
+            Self::Sellmeier { b, c } => {
+                let sum = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum::<f32>();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
@@ -4,6 +4,7 @@ use crate::Vec3;
 use palette::LinSrgb;
 use rand::random;
 use std::f32::consts::PI;
+use std::io;
 use crate::hit::Hit;
 
 #[allow(unused_macros)]
@@ -97,7 +98,7 @@ pub fn compute_fresnel(ray_dir: Vec3, normal: Vec3, ior1: f32, ior2: f32) -> f32
 
 
 
-fn local_to_world(local: Vec3, u: Vec3, v: Vec3, w: Vec3) -> Vec3 {
+pub(crate) fn local_to_world(local: Vec3, u: Vec3, v: Vec3, w: Vec3) -> Vec3 {
     let [x, y, z] = local.to_array();
     x * (u) + y * (v) + z * (w)
 }
@@ -178,4 +179,37 @@ pub(crate) fn fix_normal(normal: Vec3, direction: Vec3) -> Vec3 {
     } else {
         normal
     }
-}
\ No newline at end of file
+}
+
+/// Samples a scattered direction from the Henyey-Greenstein phase function.
+/// `direction` is the direction of travel, positive `g` scatters forwards and negative `g` backwards.
+pub fn sample_henyey_greenstein(direction: Vec3, g: f32) -> Vec3 {
+    let r1: f32 = random();
+    let r2: f32 = random();
+
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * r1
+    } else {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * r1);
+        ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * r2;
+
+    let local = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+    let (a, b, c) = build_orthonormal_basis(direction);
+    local_to_world(local, a, b, c)
+}
+
+/// Free-flight distance through a homogeneous medium, exponentially distributed with the given extinction
+pub fn sample_free_flight(extinction: f32) -> f32 {
+    if extinction <= 0.0 {
+        return f32::INFINITY;
+    }
+    -(1.0 - random::<f32>()).ln() / extinction
+}
+
+/// Error for files that were read fine but don't contain what we expected
+pub(crate) fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}